@@ -0,0 +1,302 @@
+use std::env;
+use std::io::Write;
+
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use dotenv::dotenv;
+use log::info;
+use negi::ErrorInterface;
+use negi::export::{
+	apps::write_app_csv,
+	journal::{JournalFormat, JournalOptions, write_journal},
+	ofx::write_ofx,
+	select_rows,
+};
+use negi::log::setup_logger;
+use negi::sheet::auth::get_sheets_client;
+use negi::sheet::fetch::fetch_from_sheet;
+use negi::sheet::write::{append_to_sheet, set_statuses_in_sheet};
+use negi::statement::{
+	LAYOUTS,
+	apps::{APP_TIMEZONE, App, AppMaps, read_app_csv},
+	find_layout,
+	matching::{DEFAULT_DATE_TOLERANCE_DAYS, find_missing, remove_known_ids},
+	ofx::{read_ofx, read_ofx_timezone},
+	read_statement,
+	reconcile::{Finding, reconcile},
+};
+use negi::transaction::Transaction;
+
+const USAGE: &str = "Usage:
+	bookkeeper import <format> <file> <account> [--dry-run]
+	bookkeeper import (moneyforward | zaim) <file> [--dry-run]
+	bookkeeper reconcile <format> <file> <account> <from> <to> [--write-status]
+	bookkeeper export ofx <account> <from> <to> [<timezone>]
+	bookkeeper export (ledger | beancount | moneyforward | zaim) [<from> <to>]
+
+<format> is ofx, moneyforward, zaim or a statement CSV layout: rakuten-card, ocbc, bca
+<timezone> is the time zone of the days from <from> to <to>, such as Asia/Jakarta, by default
+JOURNAL_TIMEZONE";
+
+#[tokio::main]
+async fn main() -> Result<(), ErrorInterface> {
+	dotenv().ok();
+	setup_logger();
+
+	let args = env::args().skip(1).collect::<Vec<String>>();
+	let args = args.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+
+	match args.as_slice() {
+		["import", app @ ("moneyforward" | "zaim"), file] => {
+			run_import_command(app, file, None, false).await
+		}
+		["import", app @ ("moneyforward" | "zaim"), file, "--dry-run"] => {
+			run_import_command(app, file, None, true).await
+		}
+		["import", layout, file, account] => {
+			run_import_command(layout, file, Some(account), false).await
+		}
+		["import", layout, file, account, "--dry-run"] => {
+			run_import_command(layout, file, Some(account), true).await
+		}
+		["reconcile", layout, file, account, from, to] => {
+			run_reconcile_command(layout, file, account, (from, to), false).await
+		}
+		[
+			"reconcile",
+			layout,
+			file,
+			account,
+			from,
+			to,
+			"--write-status",
+		] => run_reconcile_command(layout, file, account, (from, to), true).await,
+		["export", "ofx", account, from, to] => {
+			run_export_ofx_command(account, (from, to), None).await
+		}
+		["export", "ofx", account, from, to, timezone] => {
+			run_export_ofx_command(account, (from, to), Some(timezone)).await
+		}
+		["export", format @ ("ledger" | "beancount")] => {
+			run_export_journal_command(format, None).await
+		}
+		["export", format @ ("ledger" | "beancount"), from, to] => {
+			run_export_journal_command(format, Some((from, to))).await
+		}
+		["export", app @ ("moneyforward" | "zaim")] => run_export_app_command(app, None).await,
+		["export", app @ ("moneyforward" | "zaim"), from, to] => {
+			run_export_app_command(app, Some((from, to))).await
+		}
+		_ => Err(USAGE.into()),
+	}
+}
+
+/// Reads an OFX file, a statement CSV or an app's export, returning its transactions and the time
+/// zone its dates are in. App exports name the account of each row, so only the account's rows
+/// are kept if one is given.
+async fn read_transactions(
+	format: &str,
+	file: &str,
+	account: Option<&str>,
+) -> Result<(Vec<Transaction>, chrono_tz::Tz), ErrorInterface> {
+	let contents = tokio::fs::read(file).await?;
+	if let Some(app) = App::from_name(format) {
+		let transfer_category = JournalOptions::from_env()?.transfer_category;
+		let transactions = read_app_csv(app, &contents, &AppMaps::from_env()?, &transfer_category)?
+			.into_iter()
+			.filter(|t| account.is_none_or(|a| t.account == a))
+			.collect();
+		return Ok((transactions, APP_TIMEZONE));
+	}
+
+	let account = account.ok_or(USAGE)?;
+	if format == "ofx" {
+		return Ok((read_ofx(&contents, account)?, read_ofx_timezone(&contents)?));
+	}
+
+	let layout = find_layout(format).ok_or(format!(
+		"Unknown format {}, expected ofx, moneyforward, zaim or one of: {}",
+		format,
+		LAYOUTS
+			.iter()
+			.map(|l| l.name)
+			.collect::<Vec<&str>>()
+			.join(", ")
+	))?;
+	Ok((read_statement(layout, &contents, account)?, layout.timezone))
+}
+
+/// Appends the statement's transactions that are not on the sheet yet.
+async fn run_import_command(
+	format: &str,
+	file: &str,
+	account: Option<&str>,
+	dry_run: bool,
+) -> Result<(), ErrorInterface> {
+	let (transactions, _) = read_transactions(format, file, account).await?;
+	info!("{} transactions in statement", transactions.len());
+
+	let client = get_sheets_client().await?;
+	let rows = fetch_from_sheet(&client).await?;
+	let missing = find_missing(
+		remove_known_ids(transactions, &rows),
+		&rows,
+		Duration::days(DEFAULT_DATE_TOLERANCE_DAYS),
+	);
+	info!("{} transactions missing from sheet", missing.len());
+
+	if dry_run || missing.is_empty() {
+		for transaction in &missing {
+			println!("{:?}", transaction);
+		}
+		return Ok(());
+	}
+
+	append_to_sheet(&client, missing).await?;
+	info!("Appended to sheet");
+
+	Ok(())
+}
+
+/// Reports where the sheet disagrees with the statement over the dates from `from` to `to`,
+/// both included, and optionally writes a status next to each row.
+async fn run_reconcile_command(
+	format: &str,
+	file: &str,
+	account: &str,
+	(from, to): (&str, &str),
+	write_status: bool,
+) -> Result<(), ErrorInterface> {
+	let (transactions, timezone) = read_transactions(format, file, Some(account)).await?;
+	let period = (
+		start_of_day(from, timezone)?,
+		start_of_day(to, timezone)? + Duration::days(1),
+	);
+
+	let client = get_sheets_client().await?;
+	let rows = fetch_from_sheet(&client).await?;
+	let findings = reconcile(
+		transactions,
+		&rows,
+		account,
+		period,
+		Duration::days(DEFAULT_DATE_TOLERANCE_DAYS),
+	);
+
+	for finding in &findings {
+		println!("{}", finding);
+	}
+	let count = |f: fn(&Finding) -> bool| findings.iter().filter(|x| f(x)).count();
+	info!(
+		"{} matched, {} with date drift, {} amount mismatches, {} missing, {} extra",
+		count(|f| matches!(f, Finding::Matched(..))),
+		count(|f| matches!(f, Finding::DateDrift(..))),
+		count(|f| matches!(f, Finding::AmountMismatch(..))),
+		count(|f| matches!(f, Finding::Missing(..))),
+		count(|f| matches!(f, Finding::Extra(..))),
+	);
+
+	if write_status {
+		let column = env::var("RECONCILE_STATUS_COLUMN").unwrap_or(String::from("I"));
+		set_statuses_in_sheet(
+			&client,
+			&column,
+			findings.iter().filter_map(|f| f.status()).collect(),
+		)
+		.await?;
+		info!("Wrote statuses to column {}", column);
+	}
+
+	Ok(())
+}
+
+/// Prints the account's rows from `from` to `to`, both included, as an OFX statement. The days
+/// are in the given time zone, or in the journal's if none is given.
+async fn run_export_ofx_command(
+	account: &str,
+	(from, to): (&str, &str),
+	timezone: Option<&str>,
+) -> Result<(), ErrorInterface> {
+	let timezone = match timezone {
+		Some(timezone) => timezone.parse::<chrono_tz::Tz>()?,
+		None => JournalOptions::from_env()?.timezone,
+	};
+	let period = (
+		start_of_day(from, timezone)?,
+		start_of_day(to, timezone)? + Duration::days(1),
+	);
+
+	let client = get_sheets_client().await?;
+	let rows = fetch_from_sheet(&client).await?;
+	let selected = select_rows(&rows, Some(account), period);
+	info!("Exporting {} rows", selected.len());
+
+	print!("{}", write_ofx(&selected, account, period));
+
+	Ok(())
+}
+
+/// Prints every account's rows as an hledger/ledger or Beancount journal, optionally only those
+/// from `from` to `to`, both included.
+async fn run_export_journal_command(
+	format: &str,
+	period: Option<(&str, &str)>,
+) -> Result<(), ErrorInterface> {
+	let options = JournalOptions::from_env()?;
+	let format = match format {
+		"beancount" => JournalFormat::Beancount,
+		_ => JournalFormat::Ledger,
+	};
+	let period = match period {
+		Some((from, to)) => (
+			start_of_day(from, options.timezone)?,
+			start_of_day(to, options.timezone)? + Duration::days(1),
+		),
+		None => (DateTime::<Utc>::MIN_UTC, DateTime::<Utc>::MAX_UTC),
+	};
+
+	let client = get_sheets_client().await?;
+	let rows = fetch_from_sheet(&client).await?;
+	let selected = select_rows(&rows, None, period);
+	info!("Exporting {} rows", selected.len());
+
+	print!("{}", write_journal(&selected, &options, format));
+
+	Ok(())
+}
+
+/// Writes every account's rows in the app's CSV format to the standard output, optionally only
+/// those from `from` to `to`, both included.
+async fn run_export_app_command(
+	app_name: &str,
+	period: Option<(&str, &str)>,
+) -> Result<(), ErrorInterface> {
+	let app = App::from_name(app_name).ok_or(USAGE)?;
+	let maps = AppMaps::from_env()?;
+	let transfer_category = JournalOptions::from_env()?.transfer_category;
+	let period = match period {
+		Some((from, to)) => (
+			start_of_day(from, APP_TIMEZONE)?,
+			start_of_day(to, APP_TIMEZONE)? + Duration::days(1),
+		),
+		None => (DateTime::<Utc>::MIN_UTC, DateTime::<Utc>::MAX_UTC),
+	};
+
+	let client = get_sheets_client().await?;
+	let rows = fetch_from_sheet(&client).await?;
+	let selected = select_rows(&rows, None, period);
+	info!("Exporting {} rows", selected.len());
+
+	std::io::stdout().write_all(&write_app_csv(app, &selected, &maps, &transfer_category))?;
+
+	Ok(())
+}
+
+fn start_of_day(date: &str, timezone: chrono_tz::Tz) -> Result<DateTime<Utc>, ErrorInterface> {
+	let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")?;
+	let datetime = timezone
+		.from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+		.single()
+		.ok_or("Ambiguous date")?;
+
+	Ok(datetime.with_timezone(&Utc))
+}
@@ -1,6 +1,7 @@
 #[macro_use]
 extern crate rocket;
 
+use std::collections::BTreeMap;
 use std::env;
 use std::net::Ipv4Addr;
 use std::path::Path;
@@ -57,6 +58,9 @@ async fn submit(input: Json<InputData>) -> Status {
 		datetime: data.datetime,
 		amount,
 		subject: data.subject,
+		category: None,
+		receipt: None,
+		metadata: BTreeMap::new(),
 	}];
 
 	let sheets_client = get_sheets_client().await;
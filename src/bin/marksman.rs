@@ -1,18 +1,16 @@
 use std::collections::HashMap;
-use std::env;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::Path;
 
 use dotenv::dotenv;
-use log::{error, info, warn};
+use log::{error, info};
 use negi::ErrorInterface;
+use negi::category::{CategoryMap, read_category_map};
 use negi::log::setup_logger;
 use negi::sheet::ValueRow;
 use negi::sheet::auth::get_sheets_client;
 use negi::sheet::fetch::fetch_from_sheet;
 use negi::sheet::write::{mark_duplicates_in_sheet, set_categories_in_sheet};
 use reqwest::Client;
+use rust_decimal::Decimal;
 
 #[tokio::main]
 async fn main() -> Result<(), ErrorInterface> {
@@ -44,7 +42,7 @@ async fn mark_duplicates(client: &Client, values: Vec<ValueRow>) {
 	};
 }
 
-type GroupedMap = HashMap<i64, Vec<ValueRow>>;
+type GroupedMap = HashMap<Decimal, Vec<ValueRow>>;
 
 fn make_grouped_map(values: Vec<ValueRow>) -> GroupedMap {
 	let mut map = HashMap::new();
@@ -172,42 +170,12 @@ async fn set_categories(client: &Client, values: Vec<ValueRow>) {
 	};
 }
 
-type CategoryMap = HashMap<String, String>;
-
-fn read_category_map() -> Result<CategoryMap, ErrorInterface> {
-	let category_map_file_path = env::var("CATEGORY_MAP_FILE")?;
-	let path = Path::new(&category_map_file_path);
-	let file = File::open(path)?;
-	let reader = BufReader::new(file);
-
-	let mut map: CategoryMap = HashMap::new();
-
-	for (line_num, line_result) in reader.lines().enumerate() {
-		let line = line_result?;
-		let trimmed_line = line.trim();
-
-		if trimmed_line.is_empty() {
-			continue;
-		}
-
-		let parts: Vec<&str> = trimmed_line.split(',').collect();
-
-		if parts.len() != 2 {
-			warn!("Line {} has expected number of items", line_num + 1);
-			continue;
-		}
-
-		map.insert(parts[0].to_string(), parts[1].to_string());
-	}
-
-	Ok(map)
-}
-
 fn match_subject_to_categories(values: Vec<ValueRow>, category_map: &CategoryMap) -> Vec<ValueRow> {
 	values
 		.into_iter()
-		// filter out items already having a category and those without subjects
-		.filter(|i| i.subject.len() > 0 && i.category.len() < 1)
+		// filter out items already having a category (e.g. one suggested by Gemini) and those
+		// without subjects
+		.filter(|i| i.subject.len() > 0 && i.category.trim().is_empty())
 		// set the category if subject contains the keyword
 		.map(|mut i| {
 			for (k, v) in category_map.iter() {
@@ -227,8 +195,12 @@ fn match_subject_to_categories(values: Vec<ValueRow>, category_map: &CategoryMap
 
 #[cfg(test)]
 mod tests {
+	use std::collections::HashMap;
+
+	use rust_decimal::Decimal;
+
 	use super::ValueRow;
-	use super::should_flip_by_time;
+	use super::{match_subject_to_categories, should_flip_by_time};
 
 	#[test]
 	fn flips_when_earlier_row_is_on_the_hour_and_later_row_has_nonzero_minutes_or_seconds() {
@@ -240,18 +212,51 @@ mod tests {
 			account: "Bank".to_string(),
 			subject: "".to_string(),
 			date_value: CURRENT_DATE_VALUE,
-			amount: 1000,
+			amount: Decimal::from(1000),
+			currency: "".to_string(),
 			category: "".to_string(),
+			notes: "".to_string(),
 		};
 		let next = ValueRow {
 			row_number: 3,
 			account: "Bank".to_string(),
 			subject: "".to_string(),
 			date_value: NEXT_DATE_VALUE,
-			amount: 1000,
+			amount: Decimal::from(1000),
+			currency: "".to_string(),
 			category: "".to_string(),
+			notes: "".to_string(),
 		};
 
 		assert!(should_flip_by_time(&current, &next));
 	}
+
+	#[test]
+	fn only_rows_without_a_category_are_filled() {
+		let make_row = |row_number: usize, subject: &str, category: &str| ValueRow {
+			row_number,
+			account: "Rakuten".to_string(),
+			subject: subject.to_string(),
+			date_value: 0.0,
+			amount: Decimal::from(-1000),
+			currency: "".to_string(),
+			category: category.to_string(),
+			notes: "".to_string(),
+		};
+		let values = vec![
+			make_row(2, "ファミリーマート 西神中央店", ""),
+			make_row(3, "ファミリーマート 三宮店", "Snacks"),
+			make_row(4, "ファミリーマート 三宮店", " "),
+			make_row(5, "Steam", ""),
+		];
+		let mut category_map = HashMap::new();
+		category_map.insert("ファミリーマート".to_string(), "Groceries".to_string());
+
+		let matched = match_subject_to_categories(values, &category_map);
+		assert_eq!(
+			vec![2, 4],
+			matched.iter().map(|r| r.row_number).collect::<Vec<usize>>()
+		);
+		assert!(matched.iter().all(|r| r.category == "Groceries"));
+	}
 }
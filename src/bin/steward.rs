@@ -0,0 +1,115 @@
+use std::env;
+
+use dotenv::dotenv;
+use log::info;
+use negi::ErrorInterface;
+use negi::log::setup_logger;
+use negi::mail::parsers::gemini::PROMPT_VERSION;
+use negi::mail::parsers::gemini::cache::{ParseCache, PurgeFilter};
+use negi::mail::parsers::gemini::usage::UsageLedger;
+use rust_decimal::Decimal;
+
+const USAGE: &str = "Usage:
+	steward cache list
+	steward cache show <key>
+	steward cache purge (--all | --expired | --stale | --model <model> | <key>)
+	steward usage [<days>]";
+
+#[tokio::main]
+async fn main() -> Result<(), ErrorInterface> {
+	dotenv().ok();
+	setup_logger();
+
+	let args = env::args().skip(1).collect::<Vec<String>>();
+	let args = args.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+
+	match args.as_slice() {
+		["cache", rest @ ..] => run_cache_command(rest).await,
+		["usage"] => run_usage_command(31),
+		["usage", days] => run_usage_command(days.parse::<i64>()?),
+		_ => Err(USAGE.into()),
+	}
+}
+
+async fn run_cache_command(args: &[&str]) -> Result<(), ErrorInterface> {
+	let cache = ParseCache::from_env().ok_or("GEMINI_CACHE_DIR must be set")?;
+
+	match args {
+		["list"] => {
+			let entries = cache.entries().await?;
+			for entry in &entries {
+				println!(
+					"{}  {}  {}  v{}{}  {} transaction(s)  [{}]",
+					entry.key.get(..12).unwrap_or(&entry.key),
+					entry.created_at.format("%Y-%m-%d %H:%M:%S"),
+					entry.model,
+					entry.prompt_version,
+					if cache.is_expired(entry) {
+						" (expired)"
+					} else {
+						""
+					},
+					entry.transactions.len(),
+					entry.mail_subject,
+				);
+			}
+			info!("{} cached results", entries.len());
+		}
+		["show", key] => {
+			let entry = cache.find(key).await?;
+			println!("{}", serde_json::to_string_pretty(&entry)?);
+		}
+		["purge", filter] | ["purge", filter, _] => {
+			let filter = match (*filter, args.get(2)) {
+				("--all", None) => PurgeFilter::All,
+				("--expired", None) => PurgeFilter::Expired,
+				("--stale", None) => PurgeFilter::StalePromptVersion(PROMPT_VERSION),
+				("--model", Some(model)) => PurgeFilter::Model(model.to_string()),
+				(key, None) if !key.starts_with("--") => {
+					PurgeFilter::Key(cache.find(key).await?.key)
+				}
+				_ => return Err(USAGE.into()),
+			};
+			let removed = cache.purge(&filter).await?;
+			info!("Purged {} cached results", removed);
+		}
+		_ => return Err(USAGE.into()),
+	}
+
+	Ok(())
+}
+
+fn run_usage_command(days: i64) -> Result<(), ErrorInterface> {
+	let ledger = UsageLedger::from_env()?.ok_or("GEMINI_USAGE_FILE must be set")?;
+	let since = chrono::Utc::now().date_naive() - chrono::Duration::days(days - 1);
+
+	let records = ledger.records()?;
+	let mut total_cost = Decimal::ZERO;
+	for ((date, model), usage) in ledger.daily_usage(&records) {
+		if date < since {
+			continue;
+		}
+
+		println!(
+			"{}  {}  {} call(s)  {} input / {} output tokens  ${}",
+			date,
+			model,
+			usage.calls,
+			usage.input_tokens,
+			usage.output_tokens,
+			usage.cost.round_dp(4),
+		);
+		total_cost += usage.cost;
+	}
+	info!(
+		"Estimated cost for the last {} day(s): ${}",
+		days,
+		total_cost.round_dp(4)
+	);
+
+	if let Some(reason) = ledger.budget_exceeded(&[])? {
+		info!("Budget exceeded: {}", reason);
+	}
+
+	Ok(())
+}
@@ -3,22 +3,39 @@ use std::sync::Arc;
 
 use ::log::info;
 use dotenv::dotenv;
-use log::error;
+use log::{error, warn};
 use negi::ErrorInterface;
+use negi::category::{CategoryMap, known_categories, read_category_map};
 use negi::log::setup_logger;
 use negi::mail::parsers::parse_emails;
 use negi::mail::reader::read_emails;
 use negi::mail::{
 	Mail,
+	archive::AttachmentArchive,
 	cleaner::remove_emails,
 	parsers::{
-		EmailParsingScheme, gemini::GeminiParsingScheme, ocbc::OcbcPaymentNotificationScheme,
-		rakuten_card::RakutenCardParsingScheme, rakuten_pay::RakutenPayParsingScheme,
+		EmailParsingScheme,
+		amazon::AmazonOrderParsingScheme,
+		bca::BcaNotificationScheme,
+		digital_store::DigitalStoreReceiptScheme,
+		gemini::{
+			GeminiParsingScheme, cache::ParseCache, compaction::Compactor,
+			eligibility::EligibilityFilter, redaction::Redactor, usage::UsageLedger,
+		},
+		jenius::JeniusNotificationScheme,
+		ocbc::OcbcPaymentNotificationScheme,
+		paypay::PayPayParsingScheme,
+		rakuten_card::RakutenCardParsingScheme,
+		rakuten_pay::RakutenPayParsingScheme,
+		utility_bill::UtilityBillParsingScheme,
+		yuucho::YuuchoDebitParsingScheme,
 	},
 };
 use negi::network::ClientInterface;
 use negi::network::reqwest_client::ReqwestClient;
+use negi::receipt::{ReceiptStore, is_recorded_as_items};
 use negi::sheet::auth::get_sheets_client;
+use negi::sheet::fetch::fetch_from_sheet;
 use negi::sheet::write::append_to_sheet;
 use negi::transaction::Transaction;
 use tokio::sync::Mutex;
@@ -30,12 +47,70 @@ async fn main() -> Result<(), ErrorInterface> {
 
 	let client: ClientInterface = Arc::new(Mutex::new(ReqwestClient::new()));
 
-	let parsers = get_parsers(&client)?;
 	let mails = read_emails().await?;
-	let transactions = parse_emails(mails, &parsers).await?;
+	// only Gemini picks categories, so the sheet is not read for runs without mails
+	let gemini_configured = env::var("GEMINI_API_KEY").is_ok_and(|k| !k.is_empty());
+	let categories = match !mails.is_empty() && gemini_configured {
+		true => get_known_categories().await,
+		false => vec![],
+	};
+
+	let eligibility = EligibilityFilter::from_env()?.map(Arc::new);
+	let receipts = ReceiptStore::from_env().map(Arc::new);
+	let parsers = get_parsers(&client, &eligibility, &receipts, &categories)?;
+	let mut transactions = parse_emails(mails, &parsers).await?;
+
+	if let Some(eligibility) = &eligibility {
+		eligibility.log_report();
+	}
+
+	// archived before receipts are matched, so a mail kept for next time still has its receipt
+	if let Some(archive) = AttachmentArchive::from_env() {
+		let mut failed = vec![];
+		for (mail, transactions) in transactions.iter_mut() {
+			match archive.archive(mail, transactions).await {
+				Ok(0) => {}
+				Ok(saved) => info!("Mail: [{}]. Archived {} attachments", mail.subject, saved),
+				Err(e) => {
+					error!(
+						"Mail: [{}]. Could not archive attachments, keeping the mail for next time: {}",
+						mail.subject, e
+					);
+					failed.push(mail.file_path.clone());
+				}
+			}
+		}
+
+		// neither appended nor removed, so their attachments are not lost
+		transactions.retain(|mail, _| !failed.contains(&mail.file_path));
+	}
+
+	let mut matched_receipts = vec![];
+	if let Some(receipts) = &receipts {
+		match receipts
+			.enrich(transactions.values_mut().flatten().collect())
+			.await
+		{
+			Ok(matched) => {
+				info!("Added receipt items to {} transactions", matched.len());
+				matched_receipts = matched;
+			}
+			Err(e) => error!("Could not match receipts: {}", e),
+		}
+
+		// orders written down item by item are not counted again through the card
+		for transactions in transactions.values_mut() {
+			transactions.retain(|t| !is_recorded_as_items(t));
+		}
+	}
 
 	let transactions_count = transactions.values().map(|t| t.len()).sum::<usize>();
 	if transactions_count < 1 {
+		// mails that only had receipts in them are done with
+		if !transactions.is_empty() {
+			remove_emails(transactions.into_keys().collect()).await?;
+		}
+		remove_settled_receipts(&receipts, &matched_receipts).await;
 		info!("No transactions found. Exiting early");
 		return Ok(());
 	}
@@ -50,11 +125,13 @@ async fn main() -> Result<(), ErrorInterface> {
 		.flatten()
 		.collect::<Vec<Transaction>>();
 
-	let client = get_sheets_client().await?;
-	match append_to_sheet(&client, transactions).await {
+	let sheets_client = get_sheets_client().await?;
+	match append_to_sheet(&sheets_client, transactions).await {
 		Ok(_) => {
 			info!("Appended to sheet");
 			remove_emails(mails).await?;
+			// only now, so a failed append leaves the receipts for the retried mails
+			remove_settled_receipts(&receipts, &matched_receipts).await;
 		}
 		Err(e) => error!("Appending error: {}", e.to_string()),
 	}
@@ -62,14 +139,72 @@ async fn main() -> Result<(), ErrorInterface> {
 	Ok(())
 }
 
+async fn remove_settled_receipts(receipts: &Option<Arc<ReceiptStore>>, matched: &[String]) {
+	if let Some(receipts) = receipts
+		&& let Err(e) = receipts.remove_settled(matched).await
+	{
+		error!("Could not remove settled receipts: {}", e);
+	}
+}
+
+/// Collects the categories from the category map and the sheet, for Gemini to choose from. The
+/// run goes on without the sheet's categories if it can't be read.
+async fn get_known_categories() -> Vec<String> {
+	let category_map = read_category_map().unwrap_or_else(|e| {
+		warn!("Could not open category map: {}", e);
+		CategoryMap::new()
+	});
+	let rows = match get_sheets_client().await {
+		Ok(sheets_client) => fetch_from_sheet(&sheets_client).await,
+		Err(e) => Err(e),
+	};
+	let rows = rows.unwrap_or_else(|e| {
+		warn!("Could not fetch categories from sheet: {}", e);
+		vec![]
+	});
+
+	known_categories(&category_map, &rows)
+}
+
 fn get_parsers(
 	client_interface: &ClientInterface,
+	eligibility: &Option<Arc<EligibilityFilter>>,
+	receipts: &Option<Arc<ReceiptStore>>,
+	categories: &[String],
 ) -> Result<Vec<Box<dyn EmailParsingScheme>>, ErrorInterface> {
 	Ok(vec![
-		Box::new(get_gemini_parser(client_interface)?),
+		// receipts go first, so order mails are not taken for purchases by the parsers below
+		Box::new(AmazonOrderParsingScheme {
+			account: env::var("AMAZON_ORDER_PARSING_SCHEME_TARGET_ACCOUNT")
+				.unwrap_or(String::from("Rakuten")),
+			receipts: receipts.clone(),
+			item_transactions: env::var("AMAZON_ORDER_PARSING_SCHEME_ITEM_TRANSACTIONS")
+				.unwrap_or_default()
+				== "true",
+		}),
+		Box::new(DigitalStoreReceiptScheme {
+			receipts: receipts.clone(),
+		}),
+		Box::new(UtilityBillParsingScheme {
+			account: env::var("UTILITY_BILL_PARSING_SCHEME_TARGET_ACCOUNT")
+				.ok()
+				.filter(|s| !s.is_empty()),
+			receipts: receipts.clone(),
+		}),
+		Box::new(get_gemini_parser(
+			client_interface,
+			eligibility,
+			categories,
+		)?),
 		Box::new(RakutenPayParsingScheme {
 			account: env::var("RAKUTEN_PAY_PARSING_SCHEME_TARGET_ACCOUNT")
 				.unwrap_or(String::from("Rakuten")),
+			cash_account: env::var("RAKUTEN_PAY_PARSING_SCHEME_CASH_ACCOUNT")
+				.ok()
+				.filter(|s| !s.is_empty()),
+			points_account: env::var("RAKUTEN_PAY_PARSING_SCHEME_POINTS_ACCOUNT")
+				.ok()
+				.filter(|s| !s.is_empty()),
 		}),
 		Box::new(RakutenCardParsingScheme {
 			account: env::var("RAKUTEN_CARD_PARSING_SCHEME_TARGET_ACCOUNT")
@@ -79,15 +214,40 @@ fn get_parsers(
 			account: env::var("OCBC_PAYMENT_NOTIFICATION_PARSING_SCHEME_TARGET_ACCOUNT")
 				.unwrap_or(String::from("OCBC")),
 		}),
+		Box::new(BcaNotificationScheme {
+			account: env::var("BCA_NOTIFICATION_PARSING_SCHEME_TARGET_ACCOUNT")
+				.unwrap_or(String::from("BCA")),
+		}),
+		Box::new(JeniusNotificationScheme {
+			account: env::var("JENIUS_NOTIFICATION_PARSING_SCHEME_TARGET_ACCOUNT")
+				.unwrap_or(String::from("Jenius")),
+		}),
+		Box::new(YuuchoDebitParsingScheme {
+			account: env::var("YUUCHO_DEBIT_PARSING_SCHEME_TARGET_ACCOUNT")
+				.unwrap_or(String::from("Yuucho")),
+		}),
+		Box::new(PayPayParsingScheme {
+			account: env::var("PAYPAY_PARSING_SCHEME_TARGET_ACCOUNT")
+				.unwrap_or(String::from("PayPay")),
+		}),
 	])
 }
 
 fn get_gemini_parser(
 	client_interface: &ClientInterface,
+	eligibility: &Option<Arc<EligibilityFilter>>,
+	categories: &[String],
 ) -> Result<GeminiParsingScheme, ErrorInterface> {
 	Ok(GeminiParsingScheme {
 		client: client_interface.clone(),
-		model: env::var("GEMINI_MODEL").unwrap_or(String::from("gemini-2.5-flash")),
+		models: match env::var("GEMINI_MODELS") {
+			Ok(models) if !models.is_empty() => models
+				.split(",")
+				.map(|s| s.trim().to_owned())
+				.filter(|s| !s.is_empty())
+				.collect::<Vec<String>>(),
+			_ => vec![env::var("GEMINI_MODEL").unwrap_or(String::from("gemini-2.5-flash"))],
+		},
 		api_key: env::var("GEMINI_API_KEY")?,
 		accounts: 'gemini_target_accounts: {
 			let accounts_string = env::var("GEMINI_TARGET_ACCOUNTS");
@@ -104,6 +264,23 @@ fn get_gemini_parser(
 				.collect::<Vec<String>>();
 			Some(accounts)
 		},
+		categories: match categories.is_empty() {
+			true => None,
+			false => Some(categories.to_vec()),
+		},
 		skips: None,
+		cache: ParseCache::from_env(),
+		usage: UsageLedger::from_env()?,
+		eligibility: eligibility.clone(),
+		compactor: Some(Compactor::from_env()?),
+		redactor: Some(Redactor::from_env()?),
+		max_amount: match env::var("GEMINI_MAX_AMOUNT") {
+			Ok(max_amount) if !max_amount.is_empty() => Some(max_amount.parse::<i64>()?),
+			_ => None,
+		},
+		batch_size: match env::var("GEMINI_BATCH_SIZE") {
+			Ok(batch_size) if !batch_size.is_empty() => batch_size.parse::<usize>()?,
+			_ => 1,
+		},
 	})
 }
@@ -0,0 +1,55 @@
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use log::warn;
+
+use crate::ErrorInterface;
+use crate::sheet::ValueRow;
+
+/// Subject keywords mapped to the category given to rows whose subject contains them.
+pub type CategoryMap = HashMap<String, String>;
+
+pub fn read_category_map() -> Result<CategoryMap, ErrorInterface> {
+	let category_map_file_path = env::var("CATEGORY_MAP_FILE")?;
+	let path = Path::new(&category_map_file_path);
+	let file = File::open(path)?;
+	let reader = BufReader::new(file);
+
+	let mut map: CategoryMap = HashMap::new();
+
+	for (line_num, line_result) in reader.lines().enumerate() {
+		let line = line_result?;
+		let trimmed_line = line.trim();
+
+		if trimmed_line.is_empty() {
+			continue;
+		}
+
+		let parts: Vec<&str> = trimmed_line.split(',').collect();
+
+		if parts.len() != 2 {
+			warn!("Line {} has expected number of items", line_num + 1);
+			continue;
+		}
+
+		map.insert(parts[0].to_string(), parts[1].to_string());
+	}
+
+	Ok(map)
+}
+
+/// All the categories in use, from both the category map and the sheet, sorted.
+pub fn known_categories(category_map: &CategoryMap, rows: &[ValueRow]) -> Vec<String> {
+	category_map
+		.values()
+		.chain(rows.iter().map(|r| &r.category))
+		.map(|c| c.trim())
+		.filter(|c| !c.is_empty())
+		.map(|c| c.to_owned())
+		.collect::<BTreeSet<String>>()
+		.into_iter()
+		.collect()
+}
@@ -0,0 +1,244 @@
+use encoding_rs::SHIFT_JIS;
+use log::warn;
+
+use rust_decimal::Decimal;
+
+use crate::sheet::ValueRow;
+use crate::statement::{
+	apps::{APP_TIMEZONE, App, AppMaps},
+	csv::write_csv,
+};
+
+use super::{clean_subject, is_transfer, pair_transfers};
+
+const MONEYFORWARD_HEADER: &[&str] = &[
+	"計算対象",
+	"日付",
+	"内容",
+	"金額（円）",
+	"保有金融機関",
+	"大項目",
+	"中項目",
+	"メモ",
+	"振替",
+	"ID",
+];
+
+const ZAIM_HEADER: &[&str] = &[
+	"日付",
+	"方法",
+	"カテゴリ",
+	"カテゴリの内訳",
+	"支払元",
+	"入金先",
+	"品目",
+	"メモ",
+	"お店",
+	"通貨",
+	"収入",
+	"支出",
+	"振替",
+	"残高調整",
+	"通貨変換前の金額",
+	"集計の設定",
+];
+
+/// Writes the rows in the app's own CSV format, which it reads back: Shift_JIS for Money
+/// Forward ME and UTF-8 for Zaim. Money Forward ME only keeps yen, so rows in other currencies are
+/// left out of its file.
+pub fn write_app_csv(
+	app: App,
+	rows: &[&ValueRow],
+	maps: &AppMaps,
+	transfer_category: &str,
+) -> Vec<u8> {
+	match app {
+		App::MoneyForward => {
+			let text = write_csv(&make_moneyforward_records(rows, maps, transfer_category));
+			SHIFT_JIS.encode(&text).0.into_owned()
+		}
+		App::Zaim => write_csv(&make_zaim_records(rows, maps, transfer_category)).into_bytes(),
+	}
+}
+
+fn make_moneyforward_records(
+	rows: &[&ValueRow],
+	maps: &AppMaps,
+	transfer_category: &str,
+) -> Vec<Vec<String>> {
+	let app = App::MoneyForward;
+	let mut records = vec![MONEYFORWARD_HEADER.iter().map(|s| s.to_string()).collect()];
+
+	for row in rows {
+		if !matches!(row.currency.trim(), "JPY" | "") {
+			warn!(
+				"Leaving out row {} in {}, Money Forward ME only keeps yen",
+				row.row_number, row.currency
+			);
+			continue;
+		}
+
+		let metadata = row.metadata();
+		let (category, subcategory) = maps.category_to_app(app, row.category.trim());
+		records.push(vec![
+			"1".to_owned(),
+			row.datetime()
+				.with_timezone(&APP_TIMEZONE)
+				.format("%Y/%m/%d")
+				.to_string(),
+			clean_subject(row).to_owned(),
+			row.amount.to_string(),
+			maps.account_to_app(app, &row.account),
+			category,
+			subcategory,
+			metadata.get("memo").cloned().unwrap_or_default(),
+			match is_transfer(row, transfer_category) {
+				true => "1".to_owned(),
+				false => "0".to_owned(),
+			},
+			metadata.get("moneyforward_id").cloned().unwrap_or_default(),
+		]);
+	}
+
+	records
+}
+
+/// Zaim keeps a transfer as one row, so the two rows of a transfer on the sheet become one.
+fn make_zaim_records(
+	rows: &[&ValueRow],
+	maps: &AppMaps,
+	transfer_category: &str,
+) -> Vec<Vec<String>> {
+	let app = App::Zaim;
+	let transfer_pairs = pair_transfers(rows, transfer_category);
+	let mut records = vec![ZAIM_HEADER.iter().map(|s| s.to_string()).collect()];
+	let dash = || "-".to_owned();
+
+	for (index, row) in rows.iter().enumerate() {
+		let account = maps.account_to_app(app, &row.account);
+		let (method, category, from, to) = if is_transfer(row, transfer_category) {
+			let other = transfer_pairs
+				.get(&index)
+				.map(|i| maps.account_to_app(app, &rows[*i].account));
+			match (row.amount < Decimal::ZERO, other) {
+				// the outgoing row writes the transfer for both
+				(false, Some(_)) => continue,
+				(true, other) => ("transfer", None, account, other.unwrap_or_else(dash)),
+				(false, None) => ("transfer", None, dash(), account),
+			}
+		} else {
+			let category = Some(maps.category_to_app(app, row.category.trim()));
+			match row.amount < Decimal::ZERO {
+				true => ("payment", category, account, dash()),
+				false => ("income", category, dash(), account),
+			}
+		};
+		let (category, subcategory) = category.unwrap_or_else(|| (dash(), dash()));
+
+		let amount = row.amount.abs().to_string();
+		let zero = || "0".to_owned();
+		let (income, payment, transfer) = match method {
+			"payment" => (zero(), amount, zero()),
+			"income" => (amount, zero(), zero()),
+			_ => (zero(), zero(), amount),
+		};
+
+		let metadata = row.metadata();
+		records.push(vec![
+			row.datetime()
+				.with_timezone(&APP_TIMEZONE)
+				.format("%Y-%m-%d")
+				.to_string(),
+			method.to_owned(),
+			category,
+			subcategory,
+			from,
+			to,
+			metadata.get("items").cloned().unwrap_or_default(),
+			metadata.get("memo").cloned().unwrap_or_default(),
+			clean_subject(row).to_owned(),
+			match row.currency.trim() {
+				"" => "JPY".to_owned(),
+				currency => currency.to_owned(),
+			},
+			income,
+			payment,
+			transfer,
+			"0".to_owned(),
+			String::new(),
+			"常に集計に含める".to_owned(),
+		]);
+	}
+
+	records
+}
+
+#[cfg(test)]
+mod tests {
+	use rust_decimal::Decimal;
+
+	use crate::sheet::{ValueRow, datetime_to_serial};
+	use crate::statement::apps::{
+		App, read_app_csv,
+		tests::{make_maps, summarize},
+	};
+
+	use super::write_app_csv;
+
+	#[test]
+	fn exported_rows_read_back_the_same() {
+		let make_row = |row_number: usize,
+		                account: &str,
+		                subject: &str,
+		                amount: i64,
+		                currency: &str,
+		                category: &str,
+		                notes: &str| ValueRow {
+			row_number,
+			account: account.into(),
+			subject: subject.into(),
+			date_value: datetime_to_serial(&"2025-01-19T23:30:00Z".parse().unwrap()),
+			amount: Decimal::from(amount),
+			currency: currency.into(),
+			category: category.into(),
+			notes: notes.into(),
+		};
+		let rows = [
+			make_row(
+				2,
+				"Rakuten",
+				"ローソン",
+				-658,
+				"JPY",
+				"Food",
+				"items=おにぎり",
+			),
+			make_row(3, "Yuucho", "振替", -50000, "JPY", "Transfer", ""),
+			make_row(4, "財布", "!振替", 50000, "JPY", "Transfer", ""),
+			make_row(5, "BCA", "Gaji", 5000000, "IDR", "Salary", ""),
+		];
+		let rows = rows.iter().collect::<Vec<&ValueRow>>();
+		let maps = make_maps();
+
+		let zaim = write_app_csv(App::Zaim, &rows, &maps, "Transfer");
+		assert_eq!(
+			vec![
+				"2025-01-19 15:00:00 UTC Rakuten ローソン -658 Food items=おにぎり",
+				"2025-01-19 15:00:00 UTC Yuucho 振替 -50000 Transfer kind=transfer",
+				"2025-01-19 15:00:00 UTC 財布 振替 50000 Transfer kind=transfer",
+				"2025-01-19 15:00:00 UTC BCA Gaji 5000000 Salary -",
+			],
+			summarize(&read_app_csv(App::Zaim, &zaim, &maps, "Transfer").unwrap())
+		);
+
+		let moneyforward = write_app_csv(App::MoneyForward, &rows, &maps, "Transfer");
+		assert_eq!(
+			vec![
+				"2025-01-19 15:00:00 UTC Rakuten ローソン -658 Food -",
+				"2025-01-19 15:00:00 UTC Yuucho 振替 -50000 Transfer kind=transfer",
+				"2025-01-19 15:00:00 UTC 財布 振替 50000 Transfer kind=transfer",
+			],
+			summarize(&read_app_csv(App::MoneyForward, &moneyforward, &maps, "Transfer").unwrap())
+		);
+	}
+}
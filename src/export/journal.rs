@@ -0,0 +1,380 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use std::fs;
+
+use chrono::NaiveDate;
+use log::warn;
+
+use rust_decimal::Decimal;
+
+use crate::ErrorInterface;
+use crate::sheet::ValueRow;
+
+use super::{clean_subject, is_transfer, pair_transfers};
+
+const DEFAULT_TRANSFER_CATEGORY: &str = "Transfer";
+/// Where the other side of a transfer goes when its row on the other account is not found.
+const UNPAIRED_TRANSFER_ACCOUNT: &str = "Equity:Transfers";
+
+#[derive(Clone, Copy)]
+pub enum JournalFormat {
+	/// hledger and ledger
+	Ledger,
+	Beancount,
+}
+
+/// How rows become journal entries.
+pub struct JournalOptions {
+	/// Sheet accounts mapped to journal accounts, such as `Rakuten` to `Liabilities:Rakuten Card`.
+	/// Other accounts go under `Assets`.
+	pub accounts: HashMap<String, String>,
+	/// The category of rows that move money between our own accounts.
+	pub transfer_category: String,
+	/// The time zone the dates in the journal are in.
+	pub timezone: chrono_tz::Tz,
+}
+
+impl JournalOptions {
+	/// Reads the options from `JOURNAL_ACCOUNT_MAP_FILE`, `JOURNAL_TRANSFER_CATEGORY` and
+	/// `JOURNAL_TIMEZONE`, all of which may be left out.
+	pub fn from_env() -> Result<Self, ErrorInterface> {
+		let accounts = match env::var("JOURNAL_ACCOUNT_MAP_FILE") {
+			Ok(path) if !path.is_empty() => read_account_map(&fs::read_to_string(path)?),
+			_ => HashMap::new(),
+		};
+		let timezone = match env::var("JOURNAL_TIMEZONE") {
+			Ok(timezone) if !timezone.is_empty() => timezone.parse::<chrono_tz::Tz>()?,
+			_ => chrono_tz::UTC,
+		};
+
+		Ok(Self {
+			accounts,
+			transfer_category: env::var("JOURNAL_TRANSFER_CATEGORY")
+				.ok()
+				.filter(|c| !c.is_empty())
+				.unwrap_or(String::from(DEFAULT_TRANSFER_CATEGORY)),
+			timezone,
+		})
+	}
+
+	fn journal_account(&self, account: &str) -> String {
+		match self.accounts.get(account.trim()) {
+			Some(mapped) => mapped.clone(),
+			None => format!("Assets:{}", account.trim()),
+		}
+	}
+
+	fn is_transfer(&self, row: &ValueRow) -> bool {
+		is_transfer(row, &self.transfer_category)
+	}
+}
+
+/// Reads `sheet account,journal account` lines.
+fn read_account_map(contents: &str) -> HashMap<String, String> {
+	let mut map = HashMap::new();
+
+	for (line_num, line) in contents.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() {
+			continue;
+		}
+
+		let Some((account, journal_account)) = line.split_once(',') else {
+			warn!(
+				"Line {} of the account map has no journal account",
+				line_num + 1
+			);
+			continue;
+		};
+		map.insert(account.trim().to_owned(), journal_account.trim().to_owned());
+	}
+
+	map
+}
+
+struct Entry {
+	date: NaiveDate,
+	payee: String,
+	metadata: BTreeMap<String, String>,
+	/// The account, amount and currency of each posting.
+	postings: Vec<(String, Decimal, String)>,
+}
+
+/// Writes the rows as a journal, one entry per row, except for transfers whose rows on both
+/// accounts are found, which make one entry together. Rows come in the order they happened and
+/// nothing else goes into the output, so exporting the same rows gives the same journal.
+pub fn write_journal(
+	rows: &[&ValueRow],
+	options: &JournalOptions,
+	format: JournalFormat,
+) -> String {
+	let entries = make_entries(rows, options);
+
+	let mut journal = String::new();
+	if let JournalFormat::Beancount = format {
+		let accounts = entries
+			.iter()
+			.flat_map(|e| {
+				e.postings
+					.iter()
+					.map(|(account, _, _)| beancount_account(account))
+			})
+			.collect::<BTreeSet<String>>();
+		let first_date = entries.first().map(|e| e.date).unwrap_or_default();
+		for account in accounts {
+			journal.push_str(&format!("{} open {}\n", first_date, account));
+		}
+		journal.push('\n');
+	}
+
+	for entry in entries {
+		match format {
+			JournalFormat::Ledger => {
+				journal.push_str(&format!("{} {}\n", entry.date, entry.payee));
+				for (key, value) in &entry.metadata {
+					journal.push_str(&format!("    ; {}: {}\n", key, value));
+				}
+				for (account, amount, currency) in &entry.postings {
+					journal.push_str(&format!("    {}  {} {}\n", account, amount, currency));
+				}
+			}
+			JournalFormat::Beancount => {
+				journal.push_str(&format!(
+					"{} * \"{}\"\n",
+					entry.date,
+					escape_beancount(&entry.payee)
+				));
+				for (key, value) in &entry.metadata {
+					journal.push_str(&format!("  {}: \"{}\"\n", key, escape_beancount(value)));
+				}
+				for (account, amount, currency) in &entry.postings {
+					journal.push_str(&format!(
+						"  {}  {} {}\n",
+						beancount_account(account),
+						amount,
+						currency
+					));
+				}
+			}
+		}
+		journal.push('\n');
+	}
+
+	journal
+}
+
+fn make_entries(rows: &[&ValueRow], options: &JournalOptions) -> Vec<Entry> {
+	let transfer_pairs = pair_transfers(rows, &options.transfer_category);
+	let mut entries = vec![];
+
+	for (index, row) in rows.iter().enumerate() {
+		let currency = match row.currency.trim() {
+			"" => {
+				warn!("Row {} has no currency", row.row_number);
+				"XXX".to_owned()
+			}
+			currency => currency.to_owned(),
+		};
+		let account = options.journal_account(&row.account);
+		let mut metadata = row.metadata();
+
+		let other_account = if options.is_transfer(row) {
+			match transfer_pairs.get(&index) {
+				// the entry is written for the outgoing row
+				Some(_) if row.amount > Decimal::ZERO => continue,
+				Some(other) => {
+					metadata.extend(rows[*other].metadata());
+					options.journal_account(&rows[*other].account)
+				}
+				None => UNPAIRED_TRANSFER_ACCOUNT.to_owned(),
+			}
+		} else {
+			let category = match row.category.trim() {
+				"" => "Uncategorized",
+				category => category,
+			};
+			let refund = metadata.get("kind").is_some_and(|k| k == "refund");
+			match row.amount <= Decimal::ZERO || refund {
+				true => format!("Expenses:{}", category),
+				false => format!("Income:{}", category),
+			}
+		};
+
+		entries.push(Entry {
+			date: row.datetime().with_timezone(&options.timezone).date_naive(),
+			payee: clean_subject(row).to_owned(),
+			metadata,
+			postings: vec![
+				(other_account, -row.amount, currency.clone()),
+				(account, row.amount, currency),
+			],
+		});
+	}
+
+	entries
+}
+
+/// Beancount account names are made of capitalized parts without spaces.
+fn beancount_account(account: &str) -> String {
+	account
+		.split(':')
+		.map(|part| {
+			let part = part
+				.trim()
+				.chars()
+				.map(|c| match c.is_alphanumeric() || c == '-' {
+					true => c,
+					false => '-',
+				})
+				.collect::<String>();
+			let mut chars = part.chars();
+			match chars.next() {
+				Some(first) if first.is_ascii_lowercase() => {
+					first.to_ascii_uppercase().to_string() + chars.as_str()
+				}
+				Some(first) if first.is_ascii_alphanumeric() => part.clone(),
+				// parts have to start with a letter or a digit
+				_ => format!("X{}", part),
+			}
+		})
+		.collect::<Vec<String>>()
+		.join(":")
+}
+
+fn escape_beancount(text: &str) -> String {
+	text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+
+	use rust_decimal::Decimal;
+
+	use crate::sheet::{ValueRow, datetime_to_serial};
+
+	use super::{JournalFormat, JournalOptions, write_journal};
+
+	#[test]
+	fn writes_ledger_and_beancount_journals() {
+		let make_row = |row_number: usize,
+		                account: &str,
+		                subject: &str,
+		                datetime: &str,
+		                amount: i64,
+		                currency: &str,
+		                category: &str,
+		                notes: &str| ValueRow {
+			row_number,
+			account: account.into(),
+			subject: subject.into(),
+			date_value: datetime_to_serial(&datetime.parse().unwrap()),
+			amount: Decimal::from(amount),
+			currency: currency.into(),
+			category: category.into(),
+			notes: notes.into(),
+		};
+		let rows = [
+			make_row(
+				2,
+				"Rakuten",
+				"LAWSON",
+				"2025-01-19T23:30:00Z",
+				-658,
+				"JPY",
+				"Food",
+				"",
+			),
+			make_row(
+				3,
+				"Yuucho",
+				"振込 楽天カード",
+				"2025-01-27T01:00:00Z",
+				-658,
+				"JPY",
+				"Transfer",
+				"",
+			),
+			make_row(
+				4,
+				"Rakuten",
+				"!ご入金",
+				"2025-01-27T03:00:00Z",
+				658,
+				"JPY",
+				"Transfer",
+				"",
+			),
+			make_row(
+				5,
+				"Rakuten",
+				"\"Shop\"",
+				"2025-01-28T00:00:00Z",
+				1200,
+				"JPY",
+				"Clothes",
+				"kind=refund",
+			),
+			make_row(
+				6,
+				"BCA",
+				"Gaji",
+				"2025-01-31T02:00:00Z",
+				5000000,
+				"IDR",
+				"",
+				"reference=A1",
+			),
+		];
+		let rows = rows.iter().collect::<Vec<&ValueRow>>();
+		let options = JournalOptions {
+			accounts: HashMap::from([(
+				"Rakuten".to_owned(),
+				"Liabilities:Rakuten Card".to_owned(),
+			)]),
+			transfer_category: "Transfer".to_owned(),
+			timezone: chrono_tz::Asia::Tokyo,
+		};
+
+		assert_eq!(
+			"2025-01-20 LAWSON
+    Expenses:Food  658 JPY
+    Liabilities:Rakuten Card  -658 JPY
+
+2025-01-27 振込 楽天カード
+    Liabilities:Rakuten Card  658 JPY
+    Assets:Yuucho  -658 JPY
+
+2025-01-28 \"Shop\"
+    ; kind: refund
+    Expenses:Clothes  -1200 JPY
+    Liabilities:Rakuten Card  1200 JPY
+
+2025-01-31 Gaji
+    ; reference: A1
+    Income:Uncategorized  -5000000 IDR
+    Assets:BCA  5000000 IDR
+
+",
+			write_journal(&rows, &options, JournalFormat::Ledger)
+		);
+
+		assert_eq!(
+			"2025-01-20 open Assets:Yuucho
+2025-01-20 open Equity:Transfers
+2025-01-20 open Expenses:Food
+2025-01-20 open Liabilities:Rakuten-Card
+
+2025-01-20 * \"LAWSON\"
+  Expenses:Food  658 JPY
+  Liabilities:Rakuten-Card  -658 JPY
+
+2025-01-27 * \"振込 楽天カード\"
+  Equity:Transfers  658 JPY
+  Assets:Yuucho  -658 JPY
+
+",
+			write_journal(&rows[..2], &options, JournalFormat::Beancount)
+		);
+	}
+}
@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+
+use rust_decimal::Decimal;
+
+use crate::sheet::ValueRow;
+
+pub mod apps;
+pub mod journal;
+pub mod ofx;
+
+/// How many days apart the two rows of a transfer may be dated.
+const TRANSFER_MATCH_DAYS: i64 = 3;
+
+/// The rows to export, in the order they happened: those of the account, or of every account,
+/// dated from `start` up to `end`. Rows marked as duplicates are left out.
+pub fn select_rows<'a>(
+	rows: &'a [ValueRow],
+	account: Option<&str>,
+	(start, end): (DateTime<Utc>, DateTime<Utc>),
+) -> Vec<&'a ValueRow> {
+	let mut selected = rows
+		.iter()
+		.filter(|r| {
+			!r.marked_dup()
+				&& account.is_none_or(|a| r.account.trim() == a.trim())
+				&& r.datetime() >= start
+				&& r.datetime() < end
+		})
+		.collect::<Vec<&ValueRow>>();
+	selected.sort_by(|a, b| {
+		a.date_value
+			.total_cmp(&b.date_value)
+			.then(a.row_number.cmp(&b.row_number))
+	});
+
+	selected
+}
+
+/// Pairs each outgoing transfer with the incoming one on another account for the same amount,
+/// dated closest. Both rows are keyed by their index, each pointing to the other.
+pub fn pair_transfers(rows: &[&ValueRow], transfer_category: &str) -> HashMap<usize, usize> {
+	let mut pairs = HashMap::new();
+
+	for (out_index, out_row) in rows.iter().enumerate() {
+		if !is_transfer(out_row, transfer_category) || out_row.amount >= Decimal::ZERO {
+			continue;
+		}
+
+		let in_index = rows
+			.iter()
+			.enumerate()
+			.filter(|(i, in_row)| {
+				!pairs.contains_key(i)
+					&& is_transfer(in_row, transfer_category)
+					&& in_row.amount == -out_row.amount
+					&& in_row.currency == out_row.currency
+					&& in_row.account.trim() != out_row.account.trim()
+					&& (in_row.datetime() - out_row.datetime()).abs()
+						<= Duration::days(TRANSFER_MATCH_DAYS)
+			})
+			.min_by_key(|(_, in_row)| (in_row.datetime() - out_row.datetime()).abs())
+			.map(|(i, _)| i);
+
+		if let Some(in_index) = in_index {
+			pairs.insert(out_index, in_index);
+			pairs.insert(in_index, out_index);
+		}
+	}
+
+	pairs
+}
+
+pub fn is_transfer(row: &ValueRow, transfer_category: &str) -> bool {
+	row.category.trim().eq_ignore_ascii_case(transfer_category)
+}
+
+/// The subject without the `!` that marks a row as not a duplicate.
+fn clean_subject(row: &ValueRow) -> &str {
+	row.subject.trim_start_matches('!').trim()
+}
@@ -0,0 +1,162 @@
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+
+use rust_decimal::Decimal;
+
+use crate::sheet::ValueRow;
+
+use super::clean_subject;
+
+/// Writes the rows as an OFX 2 bank statement. Rows imported from OFX keep their `FITID`, others
+/// get one made from the row's contents, so exporting again gives the same IDs.
+pub fn write_ofx(
+	rows: &[&ValueRow],
+	account: &str,
+	(start, end): (DateTime<Utc>, DateTime<Utc>),
+) -> String {
+	let currency = rows
+		.iter()
+		.map(|r| r.currency.trim())
+		.find(|c| !c.is_empty())
+		.unwrap_or("XXX");
+
+	let mut transactions = String::new();
+	for row in rows {
+		let fitid = row
+			.metadata()
+			.remove("fitid")
+			.unwrap_or_else(|| make_fitid(row));
+		transactions.push_str(&format!(
+			"\t\t\t\t\t<STMTTRN>\n\t\t\t\t\t\t<TRNTYPE>{}</TRNTYPE>\n\t\t\t\t\t\t<DTPOSTED>{}</DTPOSTED>\n\t\t\t\t\t\t<TRNAMT>{}</TRNAMT>\n\t\t\t\t\t\t<FITID>{}</FITID>\n\t\t\t\t\t\t<NAME>{}</NAME>\n\t\t\t\t\t</STMTTRN>\n",
+			if row.amount < Decimal::ZERO { "DEBIT" } else { "CREDIT" },
+			format_datetime(&row.datetime()),
+			row.amount,
+			escape(&fitid),
+			escape(clean_subject(row)),
+		));
+	}
+
+	format!(
+		r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>
+<?OFX OFXHEADER="200" VERSION="220" SECURITY="NONE" OLDFILEUID="NONE" NEWFILEUID="NONE"?>
+<OFX>
+	<SIGNONMSGSRSV1>
+		<SONRS>
+			<STATUS>
+				<CODE>0</CODE>
+				<SEVERITY>INFO</SEVERITY>
+			</STATUS>
+			<DTSERVER>{end}</DTSERVER>
+			<LANGUAGE>ENG</LANGUAGE>
+		</SONRS>
+	</SIGNONMSGSRSV1>
+	<BANKMSGSRSV1>
+		<STMTTRNRS>
+			<TRNUID>0</TRNUID>
+			<STATUS>
+				<CODE>0</CODE>
+				<SEVERITY>INFO</SEVERITY>
+			</STATUS>
+			<STMTRS>
+				<CURDEF>{currency}</CURDEF>
+				<BANKACCTFROM>
+					<BANKID>negi</BANKID>
+					<ACCTID>{account}</ACCTID>
+					<ACCTTYPE>CHECKING</ACCTTYPE>
+				</BANKACCTFROM>
+				<BANKTRANLIST>
+					<DTSTART>{start}</DTSTART>
+					<DTEND>{end}</DTEND>
+{transactions}				</BANKTRANLIST>
+			</STMTRS>
+		</STMTTRNRS>
+	</BANKMSGSRSV1>
+</OFX>
+"#,
+		start = format_datetime(&start),
+		end = format_datetime(&end),
+		currency = escape(currency),
+		account = escape(account),
+		transactions = transactions,
+	)
+}
+
+fn make_fitid(row: &ValueRow) -> String {
+	let mut hasher = Sha256::new();
+	for part in [
+		row.account.trim(),
+		&row.date_value.to_string(),
+		&row.amount.to_string(),
+		clean_subject(row),
+	] {
+		hasher.update(part.as_bytes());
+		hasher.update([0]);
+	}
+	format!("{:x}", hasher.finalize())[..16].to_owned()
+}
+
+fn format_datetime(datetime: &DateTime<Utc>) -> String {
+	datetime.format("%Y%m%d%H%M%S[0:GMT]").to_string()
+}
+
+fn escape(text: &str) -> String {
+	text.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+	use rust_decimal::Decimal;
+
+	use crate::sheet::{ValueRow, datetime_to_serial};
+	use crate::statement::ofx::read_ofx;
+
+	use super::write_ofx;
+
+	#[test]
+	fn exported_statement_reads_back_with_the_same_ids() {
+		let make_row = |row_number: usize, subject: &str, amount: i64, notes: &str| ValueRow {
+			row_number,
+			account: "OCBC".into(),
+			subject: subject.into(),
+			date_value: datetime_to_serial(&"2025-01-20T01:15:00Z".parse().unwrap()),
+			amount: Decimal::from(amount),
+			currency: "IDR".into(),
+			category: "".into(),
+			notes: notes.into(),
+		};
+		let rows = [
+			make_row(2, "KOPI KENANGAN", -57300, "fitid=2025012001"),
+			make_row(3, "!Budi & Co", 1250000, ""),
+		];
+		let rows = rows.iter().collect::<Vec<&ValueRow>>();
+		let period = (
+			"2025-01-01T00:00:00Z".parse().unwrap(),
+			"2025-02-01T00:00:00Z".parse().unwrap(),
+		);
+
+		let ofx = write_ofx(&rows, "OCBC", period);
+		assert!(ofx.contains("<CURDEF>IDR</CURDEF>"));
+		assert_eq!(ofx, write_ofx(&rows, "OCBC", period));
+
+		let transactions = read_ofx(ofx.as_bytes(), "OCBC").unwrap();
+		assert_eq!(
+			vec![
+				"2025-01-20 01:15:00 UTC KOPI KENANGAN -57300",
+				"2025-01-20 01:15:00 UTC Budi & Co 1250000",
+			],
+			transactions
+				.iter()
+				.map(|t| format!(
+					"{} {} {}",
+					t.datetime,
+					t.subject.as_deref().unwrap(),
+					t.amount
+				))
+				.collect::<Vec<String>>()
+		);
+		assert_eq!("2025012001", transactions[0].metadata["fitid"]);
+		assert_eq!(16, transactions[1].metadata["fitid"].len());
+	}
+}
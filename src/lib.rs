@@ -1,7 +1,11 @@
+pub mod category;
+pub mod export;
 pub mod log;
 pub mod mail;
 pub mod network;
+pub mod receipt;
 pub mod sheet;
+pub mod statement;
 pub mod transaction;
 
 pub type ErrorInterface = Box<dyn std::error::Error + Send + Sync>;
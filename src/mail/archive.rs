@@ -0,0 +1,175 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+
+use crate::ErrorInterface;
+use crate::transaction::Transaction;
+
+use super::Mail;
+
+/// Keeps the receipts attached to mails under `<account>/<date>/<file name>`, since the mails
+/// themselves are removed once their transactions are on the sheet.
+pub struct AttachmentArchive {
+	pub directory: PathBuf,
+}
+
+impl AttachmentArchive {
+	/// Builds the archive from `RECEIPT_ARCHIVE_DIR`. Attachments are not kept if it is not set.
+	pub fn from_env() -> Option<Self> {
+		let directory = env::var("RECEIPT_ARCHIVE_DIR").ok()?;
+		if directory.is_empty() {
+			return None;
+		}
+
+		Some(Self {
+			directory: PathBuf::from(directory),
+		})
+	}
+
+	/// Saves the mail's attachments under its first transaction's account and date, and notes
+	/// where the first one went on every transaction. Returns how many files were saved.
+	pub async fn archive(
+		&self,
+		mail: &Mail,
+		transactions: &mut [Transaction],
+	) -> Result<usize, ErrorInterface> {
+		let Some(first) = transactions.first() else {
+			return Ok(0);
+		};
+		let relative_directory = PathBuf::from(sanitize(&first.account))
+			.join(first.datetime.format("%Y-%m-%d").to_string());
+		fs::create_dir_all(self.directory.join(&relative_directory)).await?;
+
+		let mut saved = vec![];
+		for attachment in &mail.attachments {
+			let relative_path = self
+				.free_path(
+					&relative_directory,
+					&sanitize(&attachment.filename),
+					&attachment.contents,
+				)
+				.await?;
+			fs::write(self.directory.join(&relative_path), &attachment.contents).await?;
+			saved.push(relative_path);
+		}
+
+		if let Some(receipt) = saved.first() {
+			let receipt = receipt.to_string_lossy().replace('\\', "/");
+			for transaction in transactions {
+				transaction.receipt = Some(receipt.clone());
+			}
+		}
+
+		Ok(saved.len())
+	}
+
+	/// A path for the file that does not overwrite a different file of the same name. The same
+	/// file saved again, e.g. when appending to the sheet failed last time, keeps its path.
+	async fn free_path(
+		&self,
+		relative_directory: &Path,
+		filename: &str,
+		contents: &[u8],
+	) -> Result<PathBuf, ErrorInterface> {
+		let (stem, extension) = match filename.rsplit_once('.') {
+			Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+			_ => (filename, String::new()),
+		};
+
+		let mut candidate = relative_directory.join(filename);
+		let mut counter = 1;
+		loop {
+			match fs::read(self.directory.join(&candidate)).await {
+				Ok(existing) if existing == contents => return Ok(candidate),
+				Ok(_) => {
+					counter += 1;
+					candidate =
+						relative_directory.join(format!("{}-{}{}", stem, counter, extension));
+				}
+				Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(candidate),
+				Err(e) => return Err(e.into()),
+			}
+		}
+	}
+}
+
+/// Makes a name safe to use as a single path component.
+fn sanitize(name: &str) -> String {
+	let sanitized = name
+		.trim()
+		.chars()
+		.map(|c| match c {
+			'/' | '\\' | ':' | '"' | '*' | '?' | '<' | '>' | '|' => '_',
+			c if c.is_control() => '_',
+			c => c,
+		})
+		.collect::<String>();
+	let sanitized = sanitized.trim_start_matches('.');
+
+	match sanitized.is_empty() {
+		true => String::from("_"),
+		false => sanitized.to_owned(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeMap;
+
+	use rust_decimal::Decimal;
+
+	use crate::mail::{Attachment, Mail};
+	use crate::transaction::Transaction;
+
+	use super::AttachmentArchive;
+
+	#[tokio::test]
+	async fn attachments_are_kept_by_account_and_date() {
+		let directory =
+			std::env::temp_dir().join(format!("negi-archive-test-{}", std::process::id()));
+		let archive = AttachmentArchive {
+			directory: directory.clone(),
+		};
+
+		let mut mail = Mail::create_test_mail();
+		mail.attachments = vec![
+			Attachment {
+				filename: "receipt.pdf".into(),
+				content_type: "application/pdf".into(),
+				contents: b"first".to_vec(),
+			},
+			Attachment {
+				filename: "../receipt.pdf".into(),
+				content_type: "application/pdf".into(),
+				contents: b"second".to_vec(),
+			},
+		];
+		let mut transactions = vec![Transaction {
+			subject: Some("Shop".into()),
+			datetime: "2025-01-20T01:15:00Z".parse().unwrap(),
+			amount: Decimal::from(-1500),
+			account: "Rakuten/Family".into(),
+			category: None,
+			metadata: BTreeMap::new(),
+			receipt: None,
+		}];
+
+		assert_eq!(2, archive.archive(&mail, &mut transactions).await.unwrap());
+		assert_eq!(
+			Some("Rakuten_Family/2025-01-20/receipt.pdf"),
+			transactions[0].receipt.as_deref()
+		);
+		let day = directory.join("Rakuten_Family/2025-01-20");
+		assert_eq!(
+			b"second".to_vec(),
+			std::fs::read(day.join("_receipt.pdf")).unwrap()
+		);
+
+		// archiving the same mail again does not make copies
+		assert_eq!(2, archive.archive(&mail, &mut transactions).await.unwrap());
+		assert_eq!(2, std::fs::read_dir(&day).unwrap().count());
+
+		std::fs::remove_dir_all(directory).unwrap();
+	}
+}
@@ -1,8 +1,11 @@
 use std::{collections::HashMap, env, path::PathBuf};
 
+use chrono::{DateTime, Utc};
+
 use crate::ErrorInterface;
 use crate::transaction::Transaction;
 
+pub mod archive;
 pub mod cleaner;
 pub mod parsers;
 pub mod reader;
@@ -12,12 +15,27 @@ pub struct RawMail {
 	pub contents: Vec<u8>,
 }
 
+/// A PDF or image file attached to a mail, such as a receipt.
+#[derive(Hash, Clone)]
+pub struct Attachment {
+	pub filename: String,
+	pub content_type: String,
+	pub contents: Vec<u8>,
+}
+
 #[derive(Hash)]
 pub struct Mail {
 	pub file_path: PathBuf,
 	pub from: String,
 	pub subject: String,
 	pub body: String,
+	/// When the mail was sent, if its date could be read.
+	pub date: Option<DateTime<Utc>>,
+	/// Who forwarded the mail to us, when `from`, `subject` and `date` are the forwarded message's.
+	pub forwarded_by: Option<String>,
+	/// What the forwarder wrote along with the forwarded message.
+	pub forward_note: Option<String>,
+	pub attachments: Vec<Attachment>,
 }
 
 impl Mail {
@@ -27,6 +45,10 @@ impl Mail {
 			from: self.from.clone(),
 			subject: self.subject.clone(),
 			body: String::new(),
+			date: self.date,
+			forwarded_by: self.forwarded_by.clone(),
+			forward_note: self.forward_note.clone(),
+			attachments: vec![],
 		}
 	}
 }
@@ -43,15 +65,33 @@ impl std::fmt::Debug for Mail {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		write!(
 			f,
-			"------- Mail ------\nFile path: {}\nFrom: {}\nSubject: {}\nBody:\n---- Body Start ---\n{}\n----- Body End ----\n-------------------",
+			"------- Mail ------\nFile path: {}\nFrom: {}\nForwarded by: {}\nDate: {}\nSubject: {}\nAttachments: {}\nBody:\n---- Body Start ---\n{}\n----- Body End ----\n-------------------",
 			self.file_path.to_str().unwrap(),
 			self.from,
+			self.forwarded_by.as_deref().unwrap_or("-"),
+			self.date.map(|d| d.to_rfc3339()).unwrap_or("-".into()),
 			self.subject,
+			self.attachments
+				.iter()
+				.map(|a| a.filename.as_str())
+				.collect::<Vec<&str>>()
+				.join(", "),
 			self.body,
 		)
 	}
 }
 
+/// Returns the lowercased address of a `Name <address>` sender, or the whole sender if it is a
+/// bare address.
+pub fn address_of(sender: &str) -> String {
+	let address = match (sender.rfind('<'), sender.rfind('>')) {
+		(Some(start), Some(end)) if start < end => &sender[start + 1..end],
+		_ => sender,
+	};
+
+	address.trim().to_lowercase()
+}
+
 pub type TransactionsParsedFromMail = HashMap<Mail, Vec<Transaction>>;
 
 pub fn get_maildir_new_path() -> Result<PathBuf, ErrorInterface> {
@@ -86,6 +126,10 @@ impl Mail {
 			from: "sender".into(),
 			subject: "subject".into(),
 			body: "contents".into(),
+			date: None,
+			forwarded_by: None,
+			forward_note: None,
+			attachments: vec![],
 		}
 	}
 }
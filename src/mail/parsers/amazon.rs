@@ -0,0 +1,267 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use rust_decimal::Decimal;
+
+use crate::ErrorInterface;
+use crate::mail::{
+	Mail,
+	parsers::{parse_jpy_amount, parse_labeled_lines},
+};
+use crate::receipt::{Receipt, ReceiptItem, ReceiptStore};
+
+use super::{EmailParsingScheme, Transaction};
+
+const STORE_NAME: &str = "Amazon.co.jp";
+
+pub struct AmazonOrderParsingScheme {
+	pub account: String,
+	/// Where orders are kept until the card transaction for them arrives.
+	pub receipts: Option<Arc<ReceiptStore>>,
+	/// Makes one transaction per item instead of adding the items to the card transaction. The
+	/// order is still kept as a receipt if there is a store, so that the card transaction is left
+	/// out instead of counting the order twice.
+	pub item_transactions: bool,
+}
+
+#[derive(Debug)]
+struct Order {
+	order_number: String,
+	datetime: DateTime<Utc>,
+	items: Vec<ReceiptItem>,
+	total: Decimal,
+}
+
+impl AmazonOrderParsingScheme {
+	fn make_receipt(&self, order: &Order) -> Receipt {
+		Receipt {
+			key: Receipt::make_key(STORE_NAME, Some(&order.order_number), &order.datetime),
+			store: STORE_NAME.to_owned(),
+			card_subjects: vec!["AMAZON".to_owned(), "ＡＭＡＺＯＮ".to_owned()],
+			order_number: Some(order.order_number.clone()),
+			datetime: order.datetime,
+			due_date: None,
+			total: order.total,
+			currency: "JPY".to_owned(),
+			items: order.items.clone(),
+			items_in_subject: false,
+			recorded_as_items: self.item_transactions,
+			metadata: BTreeMap::new(),
+		}
+	}
+
+	fn make_item_transactions(&self, order: &Order) -> Vec<Transaction> {
+		let mut metadata = BTreeMap::new();
+		metadata.insert("order_number".to_owned(), order.order_number.clone());
+
+		let mut transactions = vec![];
+		for item in &order.items {
+			let mut item_metadata = metadata.clone();
+			item_metadata.insert("quantity".to_owned(), item.quantity.to_string());
+			item_metadata.insert("unit_price".to_owned(), item.price.to_string());
+
+			transactions.push(Transaction {
+				subject: Some(item.name.clone()),
+				datetime: order.datetime,
+				amount: -(item.price * Decimal::from(item.quantity)),
+				account: self.account.clone(),
+				category: None,
+				receipt: None,
+				metadata: item_metadata,
+			});
+		}
+
+		// shipping, discounts and points make the total differ from the sum of the items
+		let items_total = transactions.iter().map(|t| -t.amount).sum::<Decimal>();
+		if items_total != order.total {
+			transactions.push(Transaction {
+				subject: Some(format!("{} 送料・割引等", STORE_NAME)),
+				datetime: order.datetime,
+				amount: items_total - order.total,
+				account: self.account.clone(),
+				category: None,
+				receipt: None,
+				metadata,
+			});
+		}
+
+		transactions
+	}
+}
+
+#[async_trait::async_trait]
+impl EmailParsingScheme for AmazonOrderParsingScheme {
+	fn can_parse(&self, mail: &Mail) -> bool {
+		mail.from.contains("@amazon.co.jp")
+			&& mail.subject.contains("ご注文")
+			&& (self.item_transactions || self.receipts.is_some())
+	}
+
+	async fn parse(&self, mail: &Mail) -> Result<Vec<Transaction>, ErrorInterface> {
+		let order = read_order(&mail.body)?;
+
+		if let Some(receipts) = &self.receipts {
+			receipts.put(&self.make_receipt(&order)).await?;
+		}
+
+		if self.item_transactions {
+			return Ok(self.make_item_transactions(&order));
+		}
+
+		// the order is recorded when the card transaction for it arrives
+		Ok(vec![])
+	}
+}
+
+fn read_order(body: &str) -> Result<Order, ErrorInterface> {
+	let fields = parse_labeled_lines(body);
+
+	let order_number = fields
+		.get("注文番号")
+		.ok_or("No order number found")?
+		.clone();
+
+	let date_string = fields.get("注文日").ok_or("No order date found")?;
+	let date = NaiveDate::parse_from_str(date_string, "%Y/%m/%d")
+		.or_else(|_| NaiveDate::parse_from_str(date_string, "%Y年%m月%d日"))?;
+	let datetime = chrono_tz::Asia::Tokyo
+		.from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+		.unwrap()
+		.with_timezone(&Utc);
+
+	let total = ["注文合計", "ご請求額", "合計"]
+		.iter()
+		.find_map(|l| fields.get(*l))
+		.and_then(|t| parse_jpy_amount(t))
+		.ok_or("No order total found")?;
+
+	// an item is its name followed by its quantity and price lines
+	let mut items = vec![];
+	let mut name: Option<String> = None;
+	let mut quantity = 1;
+	for line in body.lines().map(|l| l.trim()) {
+		match line.split_once(['：', ':']) {
+			Some((label, value)) if label.trim() == "数量" => {
+				quantity = value.trim().parse::<u32>()?;
+			}
+			Some((label, value)) if label.trim() == "価格" => {
+				let price = parse_jpy_amount(value).ok_or("Failed to parse price")?;
+				let name = name.take().ok_or("Price found without an item")?;
+				items.push(ReceiptItem {
+					name,
+					quantity,
+					price,
+				});
+				quantity = 1;
+			}
+			Some(_) => name = None,
+			None if !line.is_empty() => name = Some(line.to_owned()),
+			None => {}
+		}
+	}
+	if items.is_empty() {
+		return Err("No items found".into());
+	}
+
+	Ok(Order {
+		order_number,
+		datetime,
+		items,
+		total,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeMap;
+	use std::sync::Arc;
+
+	use chrono::Duration;
+	use rust_decimal::Decimal;
+
+	use crate::mail::Mail;
+	use crate::mail::parsers::EmailParsingScheme;
+	use crate::receipt::{ReceiptStore, is_recorded_as_items};
+	use crate::transaction::Transaction;
+
+	use super::AmazonOrderParsingScheme;
+
+	#[tokio::test]
+	async fn makes_one_transaction_per_item() {
+		let scheme = AmazonOrderParsingScheme {
+			account: "Rakuten".into(),
+			receipts: None,
+			item_transactions: true,
+		};
+
+		let mut mail = Mail::create_test_mail();
+		mail.from = "\"Amazon.co.jp\" <auto-confirm@amazon.co.jp>".into();
+		mail.subject = "Amazon.co.jpでのご注文 #503-1234567-7654321".into();
+		mail.body = include_str!("fixtures/amazon_order.txt").into();
+		assert!(scheme.can_parse(&mail));
+
+		let transactions = scheme.parse(&mail).await.unwrap();
+		let summary = transactions
+			.iter()
+			.map(|t| format!("{} {}", t.subject.as_deref().unwrap(), t.amount))
+			.collect::<Vec<String>>();
+		assert_eq!(
+			vec![
+				"Anker PowerCore 10000 -2990",
+				"ワンピース 108 (ジャンプコミックス) -1056",
+				"Amazon.co.jp 送料・割引等 -410",
+			],
+			summary
+		);
+		assert_eq!(
+			"503-1234567-7654321",
+			transactions[0].metadata["order_number"]
+		);
+		assert_eq!("2", transactions[1].metadata["quantity"]);
+		assert_eq!(
+			"2025-01-17 15:00:00 UTC",
+			transactions[0].datetime.to_string()
+		);
+	}
+
+	#[tokio::test]
+	async fn card_transaction_is_left_out_when_items_are_recorded() {
+		let directory =
+			std::env::temp_dir().join(format!("negi-amazon-items-test-{}", std::process::id()));
+		let receipts = Arc::new(ReceiptStore {
+			directory: directory.clone(),
+			match_window: Duration::days(14),
+		});
+		let scheme = AmazonOrderParsingScheme {
+			account: "Rakuten".into(),
+			receipts: Some(receipts.clone()),
+			item_transactions: true,
+		};
+
+		let mut mail = Mail::create_test_mail();
+		mail.from = "\"Amazon.co.jp\" <auto-confirm@amazon.co.jp>".into();
+		mail.subject = "Amazon.co.jpでのご注文 #503-1234567-7654321".into();
+		mail.body = include_str!("fixtures/amazon_order.txt").into();
+		let mut transactions = scheme.parse(&mail).await.unwrap();
+		assert_eq!(3, transactions.len());
+
+		let mut card = Transaction {
+			subject: Some("AMAZON.CO.JP".into()),
+			datetime: "2025-01-19T03:00:00Z".parse().unwrap(),
+			amount: Decimal::from(-4456),
+			account: "Rakuten".into(),
+			category: None,
+			receipt: None,
+			metadata: BTreeMap::new(),
+		};
+		let mut all = transactions.iter_mut().collect::<Vec<&mut Transaction>>();
+		all.push(&mut card);
+		assert_eq!(1, receipts.enrich(all).await.unwrap().len());
+
+		assert!(is_recorded_as_items(&card));
+		assert!(!transactions.iter().any(is_recorded_as_items));
+
+		std::fs::remove_dir_all(directory).unwrap();
+	}
+}
@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+
+use crate::ErrorInterface;
+use crate::mail::{
+	Mail, address_of,
+	parsers::{parse_idr_amount, parse_labeled_lines, parse_local_datetime},
+};
+
+use super::{EmailParsingScheme, Transaction};
+
+/// Transaction types that put money into the account.
+const INCOMING_TYPES: &[&str] = &["transfer masuk", "dana masuk", "incoming"];
+
+/// A credit to the account, only when it is the whole type: "Transaksi Kartu Kredit" is a card
+/// purchase.
+const CREDIT_TYPE: &str = "kredit";
+
+/// BCA sends its notifications from this domain only.
+const SENDER_DOMAIN: &str = "@bca.co.id";
+
+/// Labels that name the other side of the transaction, in order of preference.
+const COUNTERPARTY_LABELS: &[&str] = &[
+	"Nama Merchant",
+	"Nama Penerima",
+	"Nama Pengirim",
+	"Merchant",
+	"Keterangan",
+];
+
+pub struct BcaNotificationScheme {
+	pub account: String,
+}
+
+#[async_trait::async_trait]
+impl EmailParsingScheme for BcaNotificationScheme {
+	fn can_parse(&self, mail: &Mail) -> bool {
+		address_of(&mail.from).ends_with(SENDER_DOMAIN)
+			&& (mail.subject.contains("Notifikasi Transaksi")
+				|| mail.subject.contains("Internet Transaction Journal"))
+	}
+
+	async fn parse(&self, mail: &Mail) -> Result<Vec<Transaction>, ErrorInterface> {
+		let fields = parse_labeled_lines(&mail.body);
+		let find_field = |labels: &[&str]| labels.iter().find_map(|l| fields.get(*l));
+
+		let kind = find_field(&["Jenis Transaksi", "Transaction Type"])
+			.cloned()
+			.unwrap_or_default();
+		let incoming = INCOMING_TYPES
+			.iter()
+			.any(|t| kind.to_lowercase().contains(t))
+			|| kind.trim().eq_ignore_ascii_case(CREDIT_TYPE);
+
+		// Amount
+		let amount_string = find_field(&["Nominal Transaksi", "Nominal", "Total Bayar", "Amount"])
+			.ok_or("No amount data found")?;
+		let mut amount = parse_idr_amount(amount_string)?;
+		amount.set_sign_negative(!incoming);
+
+		// Datetime
+		let datetime_string =
+			find_field(&["Tanggal Transaksi", "Waktu Transaksi", "Transaction Date"])
+				.ok_or("No datetime data found")?;
+		let datetime = parse_local_datetime(
+			datetime_string,
+			&["%d/%m/%Y %H:%M:%S", "%d/%m/%Y %H:%M", "%d %b %Y %H:%M:%S"],
+			chrono_tz::Asia::Jakarta,
+		)?;
+
+		// Subject
+		let subject = find_field(COUNTERPARTY_LABELS)
+			.cloned()
+			.unwrap_or(kind.clone());
+
+		let mut metadata = BTreeMap::new();
+		if !kind.is_empty() {
+			metadata.insert("kind".to_owned(), kind);
+		}
+		if let Some(reference) = find_field(&["No. Referensi", "Nomor Referensi"]) {
+			metadata.insert("reference".to_owned(), reference.clone());
+		}
+
+		Ok(vec![Transaction {
+			subject: Some(subject),
+			datetime,
+			amount,
+			account: self.account.clone(),
+			category: None,
+			receipt: None,
+			metadata,
+		}])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::mail::Mail;
+	use crate::mail::parsers::{EmailParsingScheme, parse_idr_amount};
+
+	use super::BcaNotificationScheme;
+
+	#[tokio::test]
+	async fn parses_payments_and_incoming_transfers() {
+		let scheme = BcaNotificationScheme {
+			account: "BCA".into(),
+		};
+
+		let mut mail = Mail::create_test_mail();
+		mail.from = "BCA <bca@bca.co.id>".into();
+		mail.subject = "Notifikasi Transaksi".into();
+		mail.body = include_str!("fixtures/bca_qris.txt").into();
+		assert!(scheme.can_parse(&mail));
+
+		// only the address counts, not the display name
+		mail.from = "bca@bca.co.id <alerts@bca.co.id.example>".into();
+		assert!(!scheme.can_parse(&mail));
+		mail.from = "BCA <bca@bca.co.id>".into();
+
+		let transactions = scheme.parse(&mail).await.unwrap();
+		assert_eq!(1, transactions.len());
+		assert_eq!(
+			Some("INDOMARET SUDIRMAN".to_owned()),
+			transactions[0].subject
+		);
+		assert_eq!("-57300", transactions[0].amount.to_string());
+		assert_eq!(
+			"2025-01-12 12:20:45 UTC",
+			transactions[0].datetime.to_string()
+		);
+		assert_eq!("Pembayaran QRIS", transactions[0].metadata["kind"]);
+
+		mail.body = include_str!("fixtures/bca_transfer_in.txt").into();
+		let transactions = scheme.parse(&mail).await.unwrap();
+		assert_eq!(Some("PT MAJU JAYA".to_owned()), transactions[0].subject);
+		assert_eq!("1250000.5", transactions[0].amount.to_string());
+
+		mail.body = include_str!("fixtures/bca_qris.txt")
+			.replace("Pembayaran QRIS", "Transaksi Kartu Kredit");
+		let transactions = scheme.parse(&mail).await.unwrap();
+		assert_eq!("-57300", transactions[0].amount.to_string());
+	}
+
+	#[test]
+	fn ambiguous_idr_amounts_are_rejected() {
+		assert_eq!(
+			"1250000",
+			parse_idr_amount("Rp 1.250.000,00").unwrap().to_string()
+		);
+		assert_eq!("57300", parse_idr_amount("IDR 57.300").unwrap().to_string());
+		assert_eq!(
+			"1250000.5",
+			parse_idr_amount("1250000,50").unwrap().to_string()
+		);
+
+		assert!(parse_idr_amount("IDR 65,000.00").is_err());
+		assert!(parse_idr_amount("IDR 65,000").is_err());
+		assert!(parse_idr_amount("IDR 65.00").is_err());
+		assert!(parse_idr_amount("Rp 1.250,000").is_err());
+	}
+}
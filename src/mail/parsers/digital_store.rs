@@ -0,0 +1,282 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use chrono::{NaiveDate, TimeZone, Utc};
+use regex::Regex;
+use rust_decimal::Decimal;
+
+use crate::ErrorInterface;
+use crate::mail::{
+	Mail,
+	parsers::{parse_labeled_lines, parse_local_datetime},
+};
+use crate::receipt::{Receipt, ReceiptItem, ReceiptStore};
+
+use super::{EmailParsingScheme, Transaction};
+
+/// How one store's receipt mails look.
+struct DigitalStore {
+	name: &'static str,
+	sender: &'static str,
+	subject_keywords: &'static [&'static str],
+	card_subjects: &'static [&'static str],
+	order_labels: &'static [&'static str],
+	date_labels: &'static [&'static str],
+	datetime_formats: &'static [&'static str],
+}
+
+const STORES: &[DigitalStore] = &[
+	DigitalStore {
+		name: "Steam",
+		sender: "@steampowered.com",
+		subject_keywords: &["Steam purchase", "Steam でのご購入", "Steamでのご購入"],
+		card_subjects: &["STEAM"],
+		order_labels: &["Invoice", "請求書"],
+		date_labels: &["Date issued", "発行日"],
+		datetime_formats: &["%d %b, %Y @ %I:%M%p", "%Y年%m月%d日 %H時%M分"],
+	},
+	DigitalStore {
+		name: "App Store",
+		sender: "@email.apple.com",
+		subject_keywords: &[
+			"receipt from Apple",
+			"Apple からの領収書",
+			"Appleからの領収書",
+		],
+		card_subjects: &["APPLE"],
+		order_labels: &["Order ID", "注文番号"],
+		date_labels: &["Date", "日付"],
+		datetime_formats: &[],
+	},
+	DigitalStore {
+		name: "Google Play",
+		sender: "googleplay-noreply@google.com",
+		subject_keywords: &["Google Play Order Receipt", "Google Play のご注文明細"],
+		card_subjects: &["GOOGLE"],
+		order_labels: &["Order number", "注文番号"],
+		date_labels: &["Order date", "注文日"],
+		datetime_formats: &["%Y/%m/%d %H:%M:%S", "%b %d, %Y %I:%M:%S %p"],
+	},
+];
+
+/// Labels of lines that look like items but are not.
+const NON_ITEM_LABELS: &[&str] = &[
+	"Subtotal",
+	"Total",
+	"Tax",
+	"小計",
+	"合計",
+	"消費税",
+	"税",
+	"Payment method",
+	"お支払い方法",
+];
+
+/// Keeps Steam, App Store and Google Play receipts, so the card transactions for them can carry
+/// the names of the games and apps bought after the store's name.
+pub struct DigitalStoreReceiptScheme {
+	pub receipts: Option<Arc<ReceiptStore>>,
+}
+
+impl DigitalStoreReceiptScheme {
+	fn find_store(&self, mail: &Mail) -> Option<&'static DigitalStore> {
+		STORES.iter().find(|s| {
+			mail.from.contains(s.sender)
+				&& s.subject_keywords.iter().any(|k| mail.subject.contains(k))
+		})
+	}
+}
+
+#[async_trait::async_trait]
+impl EmailParsingScheme for DigitalStoreReceiptScheme {
+	fn can_parse(&self, mail: &Mail) -> bool {
+		self.receipts.is_some() && self.find_store(mail).is_some()
+	}
+
+	async fn parse(&self, mail: &Mail) -> Result<Vec<Transaction>, ErrorInterface> {
+		let store = self.find_store(mail).ok_or("Unknown store")?;
+		let receipts = self.receipts.as_ref().ok_or("No receipt store set")?;
+
+		receipts.put(&read_receipt(store, &mail.body)?).await?;
+
+		// the purchase is recorded when the card transaction for it arrives
+		Ok(vec![])
+	}
+}
+
+fn read_receipt(store: &DigitalStore, body: &str) -> Result<Receipt, ErrorInterface> {
+	let fields = parse_labeled_lines(body);
+	let find_field = |labels: &[&str]| labels.iter().find_map(|l| fields.get(*l));
+
+	let order_number = find_field(store.order_labels).cloned();
+
+	let date_string = find_field(store.date_labels).ok_or("No date found")?;
+	let datetime =
+		match parse_local_datetime(date_string, store.datetime_formats, chrono_tz::Asia::Tokyo) {
+			Ok(datetime) => datetime,
+			// some receipts only have the date
+			Err(_) => {
+				let date = ["%Y/%m/%d", "%Y年%m月%d日", "%b %d, %Y"]
+					.iter()
+					.find_map(|f| NaiveDate::parse_from_str(date_string.trim(), f).ok())
+					.ok_or(format!("Unknown date format: {}", date_string))?;
+				chrono_tz::Asia::Tokyo
+					.from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+					.unwrap()
+					.with_timezone(&Utc)
+			}
+		};
+
+	let item_regex = Regex::new(r"^(.+?)\s{2,}([¥￥$€][0-9.,]+|[0-9.,]+\s?円)$")?;
+	let mut items = vec![];
+	let mut currency = None;
+	let mut total = None;
+	for line in body.lines().map(|l| l.trim()) {
+		let Some(captures) = item_regex.captures(line) else {
+			continue;
+		};
+		let name = captures[1].trim().trim_end_matches([':', '：']).trim();
+		let (price, price_currency) = parse_price(&captures[2])?;
+		currency.get_or_insert(price_currency);
+
+		if ["Total", "合計"].contains(&name) {
+			total = Some(price);
+		} else if !NON_ITEM_LABELS.contains(&name) {
+			items.push(ReceiptItem {
+				name: name.to_owned(),
+				quantity: 1,
+				price,
+			});
+		}
+	}
+	if items.is_empty() {
+		return Err("No items found".into());
+	}
+
+	Ok(Receipt {
+		key: Receipt::make_key(store.name, order_number.as_deref(), &datetime),
+		store: store.name.to_owned(),
+		card_subjects: store.card_subjects.iter().map(|s| s.to_string()).collect(),
+		order_number,
+		datetime,
+		due_date: None,
+		total: total.unwrap_or(items.iter().map(|i| i.price).sum()),
+		currency: currency.unwrap_or("JPY".to_owned()),
+		items,
+		items_in_subject: true,
+		recorded_as_items: false,
+		metadata: BTreeMap::new(),
+	})
+}
+
+/// Parses a price such as `¥1,220`, `1,220円`, `$19.99` or `€9.99` into the amount and the
+/// currency code.
+fn parse_price(text: &str) -> Result<(Decimal, String), ErrorInterface> {
+	let (number, currency) = match text.trim() {
+		t if t.starts_with('$') => (&t[1..], "USD"),
+		t if t.starts_with('€') => (&t['€'.len_utf8()..], "EUR"),
+		t if t.starts_with(['¥', '￥']) => (t.trim_start_matches(['¥', '￥']), "JPY"),
+		t => (t.trim_end_matches("円").trim(), "JPY"),
+	};
+
+	Ok((
+		Decimal::from_str_exact(&number.replace(",", ""))?,
+		currency.to_owned(),
+	))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeMap;
+	use std::sync::Arc;
+
+	use chrono::Duration;
+	use rust_decimal::Decimal;
+
+	use crate::mail::Mail;
+	use crate::mail::parsers::EmailParsingScheme;
+	use crate::receipt::ReceiptStore;
+	use crate::transaction::Transaction;
+
+	use super::{DigitalStoreReceiptScheme, STORES, read_receipt};
+
+	#[test]
+	fn reads_receipts_from_every_store() {
+		let cases = [
+			(
+				0,
+				include_str!("fixtures/steam_receipt.txt"),
+				"Steam 8350 JPY ELDEN RING NIGHTREIGN; Hades II 2025-01-19 01:15:00 UTC",
+			),
+			(
+				1,
+				include_str!("fixtures/apple_receipt.txt"),
+				"App Store 1220 JPY ドラゴンクエストウォーク 480ジェム 2025-01-19 15:00:00 UTC",
+			),
+			(
+				2,
+				include_str!("fixtures/google_play_receipt.txt"),
+				"Google Play 19.99 USD Stardew Valley 2025-01-21 00:30:00 UTC",
+			),
+		];
+
+		// the time zone written on the receipt is used
+		let body = include_str!("fixtures/google_play_receipt.txt").replace("AM JST", "AM PST");
+		let receipt = read_receipt(&STORES[2], &body).unwrap();
+		assert_eq!("2025-01-21 17:30:00 UTC", receipt.datetime.to_string());
+		let body = include_str!("fixtures/google_play_receipt.txt").replace("AM JST", "AM XYZ");
+		assert!(read_receipt(&STORES[2], &body).is_err());
+
+		for (store_index, body, expected) in cases {
+			let receipt = read_receipt(&STORES[store_index], body).unwrap();
+			assert_eq!(
+				expected,
+				format!(
+					"{} {} {} {} {}",
+					receipt.store,
+					receipt.total,
+					receipt.currency,
+					receipt.item_summary(),
+					receipt.datetime
+				)
+			);
+		}
+	}
+
+	#[tokio::test]
+	async fn card_transaction_gets_the_game_names() {
+		let directory =
+			std::env::temp_dir().join(format!("negi-digital-store-test-{}", std::process::id()));
+		let receipts = Arc::new(ReceiptStore {
+			directory: directory.clone(),
+			match_window: Duration::days(3650),
+		});
+		let scheme = DigitalStoreReceiptScheme {
+			receipts: Some(receipts.clone()),
+		};
+
+		let mut mail = Mail::create_test_mail();
+		mail.from = "Steam Store <noreply@steampowered.com>".into();
+		mail.subject = "Thank you for your Steam purchase!".into();
+		mail.body = include_str!("fixtures/steam_receipt.txt").into();
+		assert!(scheme.can_parse(&mail));
+		assert!(scheme.parse(&mail).await.unwrap().is_empty());
+
+		let mut card = Transaction {
+			subject: Some("STEAMGAMES.COM".into()),
+			datetime: "2025-01-20T00:00:00Z".parse().unwrap(),
+			amount: Decimal::from(-8350),
+			account: "Rakuten".into(),
+			category: None,
+			receipt: None,
+			metadata: BTreeMap::new(),
+		};
+		assert_eq!(1, receipts.enrich(vec![&mut card]).await.unwrap().len());
+		assert_eq!(
+			Some("STEAMGAMES.COM: ELDEN RING NIGHTREIGN; Hades II".to_owned()),
+			card.subject
+		);
+
+		std::fs::remove_dir_all(directory).unwrap();
+	}
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use log::warn;
+use serde::Deserialize;
+
+use crate::ErrorInterface;
+use crate::mail::Mail;
+use crate::transaction::Transaction;
+
+use super::{GeminiParsingScheme, cached_result, wrap_mail};
+
+#[derive(Deserialize, Debug)]
+struct BatchItem {
+	mail_id: String,
+	transactions: Vec<Transaction>,
+}
+
+impl GeminiParsingScheme {
+	fn make_batch_generation_config(&self, mail_ids: &[String]) -> serde_json::Value {
+		serde_json::json!({
+			"response_mime_type": "application/json",
+			"response_schema": {
+				"type": "ARRAY",
+				"items": {
+					"type": "OBJECT",
+					"properties": {
+						"mail_id": {"type":"STRING", "format":"enum", "enum": mail_ids},
+						"transactions": self.make_transactions_schema()
+					},
+					"required": ["mail_id", "transactions"]
+				}
+			}
+		})
+	}
+
+	fn make_batch_system_instruction(&self) -> String {
+		format!(
+			"{}
+			You are given several emails this time, each between its own <mail-ID> and </mail-ID> lines.
+			Parse every email on its own and return one item per email, with the ID of the email in mail_id and its purchases in transactions.
+			Return an item with an empty transactions array for an email you can't parse.",
+			self.make_system_instruction(),
+		)
+	}
+
+	/// Parses the mails in one request with the first model. Mails missing from the response, or
+	/// all of them if the response can't be used, are parsed one by one. So are mails that should
+	/// be retried with the next model.
+	pub(super) async fn parse_batch(
+		&self,
+		mails: &[&Mail],
+	) -> Vec<Result<Vec<Transaction>, ErrorInterface>> {
+		let mut results: Vec<Option<Result<Vec<Transaction>, ErrorInterface>>> =
+			mails.iter().map(|_| None).collect();
+
+		let mut uncached = vec![];
+		for (i, mail) in mails.iter().enumerate() {
+			match self.get_cached(mail).await {
+				Some(entry) => results[i] = Some(cached_result(entry)),
+				None => uncached.push(i),
+			}
+		}
+
+		// the model to start from for the mails parsed one by one
+		let mut first_model_indexes = vec![0; mails.len()];
+
+		if uncached.len() > 1 {
+			let model = &self.models[0];
+			let uncached_mails = uncached.iter().map(|i| mails[*i]).collect::<Vec<&Mail>>();
+			match self.request_batch(model, &uncached_mails).await {
+				Ok(batch_results) => {
+					for (i, batch_result) in uncached.iter().zip(batch_results) {
+						let Some(transactions) = batch_result else {
+							continue;
+						};
+						if transactions.is_empty() && self.should_escalate(mails[*i], 0) {
+							first_model_indexes[*i] = 1;
+							continue;
+						}
+						results[*i] = Some(self.finish(mails[*i], model, transactions).await);
+					}
+				}
+				Err(e) => warn!(
+					"Batched Gemini request for {} mails failed, parsing them one by one: {}",
+					uncached_mails.len(),
+					e
+				),
+			}
+		}
+
+		let mut final_results = vec![];
+		for ((mail, result), first_model_index) in
+			mails.iter().zip(results).zip(first_model_indexes)
+		{
+			match result {
+				Some(result) => final_results.push(result),
+				None => final_results.push(self.parse_from_model(mail, first_model_index).await),
+			}
+		}
+
+		final_results
+	}
+
+	/// Returns the transactions for each mail, in the same order as the mails. A mail is `None`
+	/// if the response did not mention it.
+	async fn request_batch(
+		&self,
+		model: &str,
+		mails: &[&Mail],
+	) -> Result<Vec<Option<Vec<Transaction>>>, ErrorInterface> {
+		let mut mail_ids = vec![];
+		let mut body_lengths = vec![];
+		let mut prompt = String::from("Extract the purchases from each of these emails.");
+		for (i, mail) in mails.iter().enumerate() {
+			let mail_body = self.prepare_mail_body(mail);
+			let (mail_id, wrapped_mail) = wrap_mail(i, &mail_body);
+			prompt.push('\n');
+			prompt.push_str(&wrapped_mail);
+			mail_ids.push(mail_id);
+			body_lengths.push(mail_body.len());
+		}
+
+		let body_json = self.make_body(
+			self.make_batch_system_instruction(),
+			self.make_batch_generation_config(&mail_ids),
+			prompt,
+		);
+		let mail_files = mails
+			.iter()
+			.map(|m| m.file_path.to_string_lossy())
+			.collect::<Vec<_>>();
+		let usage_mails = mails
+			.iter()
+			.zip(&mail_files)
+			.zip(body_lengths)
+			.map(|((mail, file), length)| (file.as_ref(), mail.subject.as_str(), length))
+			.collect::<Vec<(&str, &str, usize)>>();
+		let text = self.generate(model, body_json, &usage_mails).await?;
+
+		let items = serde_json::from_str::<Vec<BatchItem>>(&text)?;
+		let mut transactions_by_id: HashMap<String, Vec<Transaction>> = HashMap::new();
+		for item in items {
+			if !mail_ids.contains(&item.mail_id) {
+				return Err(format!("Response has unknown mail ID {}", item.mail_id).into());
+			}
+			if let Err(reason) = self.validate(&item.transactions) {
+				return Err(format!("Gemini response failed validation: {}", reason).into());
+			}
+			if transactions_by_id
+				.insert(item.mail_id.clone(), item.transactions)
+				.is_some()
+			{
+				return Err(format!("Response has mail ID {} twice", item.mail_id).into());
+			}
+		}
+
+		let results = mail_ids
+			.iter()
+			.map(|mail_id| transactions_by_id.get(mail_id).cloned())
+			.collect();
+
+		Ok(results)
+	}
+}
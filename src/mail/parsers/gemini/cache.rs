@@ -0,0 +1,274 @@
+use std::env;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Duration, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+
+use crate::ErrorInterface;
+use crate::transaction::Transaction;
+
+pub struct ParseCache {
+	pub directory: PathBuf,
+	pub ttl: Option<Duration>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CacheEntry {
+	pub key: String,
+	pub model: String,
+	pub prompt_version: u32,
+	pub created_at: DateTime<Utc>,
+	pub mail_from: String,
+	pub mail_subject: String,
+	pub transactions: Vec<Transaction>,
+}
+
+pub enum PurgeFilter {
+	All,
+	Expired,
+	StalePromptVersion(u32),
+	Model(String),
+	Key(String),
+}
+
+impl ParseCache {
+	/// Builds the cache from `GEMINI_CACHE_DIR` and `GEMINI_CACHE_TTL_DAYS`. Caching is disabled
+	/// if the directory is not set.
+	pub fn from_env() -> Option<Self> {
+		let directory = env::var("GEMINI_CACHE_DIR").ok()?;
+		if directory.is_empty() {
+			return None;
+		}
+
+		let ttl = env::var("GEMINI_CACHE_TTL_DAYS")
+			.ok()
+			.and_then(|s| s.parse::<i64>().ok())
+			.map(Duration::days);
+
+		Some(Self {
+			directory: PathBuf::from(directory),
+			ttl,
+		})
+	}
+
+	/// Makes the key of a result. `config` describes the settings that change the request or how
+	/// its response is checked, so results made with other settings stop being used.
+	pub fn make_key(model: &str, prompt_version: u32, config: &str, mail_body: &str) -> String {
+		let mut hasher = Sha256::new();
+		hasher.update(model.as_bytes());
+		hasher.update([0]);
+		hasher.update(prompt_version.to_be_bytes());
+		hasher.update([0]);
+		hasher.update(config.as_bytes());
+		hasher.update([0]);
+		hasher.update(mail_body.as_bytes());
+		format!("{:x}", hasher.finalize())
+	}
+
+	pub fn is_expired(&self, entry: &CacheEntry) -> bool {
+		match self.ttl {
+			Some(ttl) => entry.created_at + ttl < Utc::now(),
+			None => false,
+		}
+	}
+
+	pub async fn get(&self, key: &str) -> Option<CacheEntry> {
+		let contents = fs::read_to_string(self.entry_path(key)).await.ok()?;
+		let entry = serde_json::from_str::<CacheEntry>(&contents).ok()?;
+		if self.is_expired(&entry) {
+			return None;
+		}
+
+		Some(entry)
+	}
+
+	pub async fn put(&self, entry: &CacheEntry) -> Result<(), ErrorInterface> {
+		fs::create_dir_all(&self.directory).await?;
+		fs::write(self.entry_path(&entry.key), serde_json::to_string(entry)?).await?;
+
+		Ok(())
+	}
+
+	pub async fn entries(&self) -> Result<Vec<CacheEntry>, ErrorInterface> {
+		let mut cache_entries = vec![];
+		if !self.directory.exists() {
+			return Ok(cache_entries);
+		}
+
+		let mut entries = fs::read_dir(&self.directory).await?;
+		while let Some(entry) = entries.next_entry().await? {
+			if entry.path().extension().is_none_or(|e| e != "json") {
+				continue;
+			}
+
+			let contents = fs::read_to_string(entry.path()).await?;
+			match serde_json::from_str::<CacheEntry>(&contents) {
+				Ok(cache_entry) => cache_entries.push(cache_entry),
+				Err(e) => warn!("Skipping unreadable cache entry {:?}: {}", entry.path(), e),
+			}
+		}
+		cache_entries.sort_by_key(|e| e.created_at);
+
+		Ok(cache_entries)
+	}
+
+	/// Finds the entry with the key, or with a key starting with the prefix when only one does.
+	pub async fn find(&self, prefix: &str) -> Result<CacheEntry, ErrorInterface> {
+		let entries = self.entries().await?;
+		if let Some(entry) = entries.iter().find(|e| e.key == prefix) {
+			return Ok(entry.clone());
+		}
+
+		let mut matches = entries
+			.into_iter()
+			.filter(|e| e.key.starts_with(prefix))
+			.collect::<Vec<CacheEntry>>();
+		match matches.len() {
+			0 => Err("No cached result with that key".into()),
+			1 => Ok(matches.remove(0)),
+			_ => Err(format!(
+				"{} cached results start with {}: {}",
+				matches.len(),
+				prefix,
+				matches
+					.iter()
+					.map(|e| e.key.as_str())
+					.collect::<Vec<&str>>()
+					.join(", ")
+			)
+			.into()),
+		}
+	}
+
+	/// Removes the entries matching the filter and returns how many were removed.
+	pub async fn purge(&self, filter: &PurgeFilter) -> Result<usize, ErrorInterface> {
+		let mut removed = 0;
+
+		for entry in self.entries().await? {
+			let matches = match filter {
+				PurgeFilter::All => true,
+				PurgeFilter::Expired => self.is_expired(&entry),
+				PurgeFilter::StalePromptVersion(current) => entry.prompt_version != *current,
+				PurgeFilter::Model(model) => entry.model == *model,
+				PurgeFilter::Key(key) => entry.key == *key,
+			};
+			if !matches {
+				continue;
+			}
+
+			fs::remove_file(self.entry_path(&entry.key)).await?;
+			removed += 1;
+		}
+
+		Ok(removed)
+	}
+
+	fn entry_path(&self, key: &str) -> PathBuf {
+		self.directory.join(format!("{}.json", key))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use chrono::{Duration, Utc};
+
+	use super::{CacheEntry, ParseCache, PurgeFilter};
+
+	fn make_entry(key: &str, prompt_version: u32, age: Duration) -> CacheEntry {
+		CacheEntry {
+			key: key.into(),
+			model: "some-model".into(),
+			prompt_version,
+			created_at: Utc::now() - age,
+			mail_from: "sender".into(),
+			mail_subject: "subject".into(),
+			transactions: vec![],
+		}
+	}
+
+	#[test]
+	fn key_changes_with_model_prompt_version_config_and_body() {
+		let key = ParseCache::make_key("model-a", 1, "config", "body");
+		assert_eq!(key, ParseCache::make_key("model-a", 1, "config", "body"));
+		assert_ne!(key, ParseCache::make_key("model-b", 1, "config", "body"));
+		assert_ne!(key, ParseCache::make_key("model-a", 2, "config", "body"));
+		assert_ne!(key, ParseCache::make_key("model-a", 1, "config!", "body"));
+		assert_ne!(key, ParseCache::make_key("model-a", 1, "config", "body!"));
+	}
+
+	#[tokio::test]
+	async fn expired_entries_are_ignored_and_purged() {
+		let directory =
+			std::env::temp_dir().join(format!("negi-cache-test-{}", std::process::id()));
+		let cache = ParseCache {
+			directory: directory.clone(),
+			ttl: Some(Duration::days(1)),
+		};
+
+		cache
+			.put(&make_entry("fresh", 1, Duration::hours(1)))
+			.await
+			.unwrap();
+		cache
+			.put(&make_entry("stale", 1, Duration::days(2)))
+			.await
+			.unwrap();
+		cache
+			.put(&make_entry("old-prompt", 0, Duration::hours(1)))
+			.await
+			.unwrap();
+
+		assert!(cache.get("fresh").await.is_some());
+		assert!(cache.get("stale").await.is_none());
+
+		assert_eq!(1, cache.purge(&PurgeFilter::Expired).await.unwrap());
+		assert_eq!(
+			1,
+			cache
+				.purge(&PurgeFilter::StalePromptVersion(1))
+				.await
+				.unwrap()
+		);
+		assert_eq!(1, cache.entries().await.unwrap().len());
+		assert_eq!(1, cache.purge(&PurgeFilter::All).await.unwrap());
+
+		std::fs::remove_dir_all(directory).unwrap();
+	}
+
+	#[tokio::test]
+	async fn keys_are_found_only_by_a_unique_prefix() {
+		let directory =
+			std::env::temp_dir().join(format!("negi-cache-find-test-{}", std::process::id()));
+		let cache = ParseCache {
+			directory: directory.clone(),
+			ttl: None,
+		};
+		for key in ["abc1", "abc2", "abd"] {
+			cache
+				.put(&make_entry(key, 1, Duration::hours(1)))
+				.await
+				.unwrap();
+		}
+
+		assert_eq!("abd", cache.find("abd").await.unwrap().key);
+		assert_eq!("abc1", cache.find("abc1").await.unwrap().key);
+		assert_eq!("abc2", cache.find("abc2").await.unwrap().key);
+		assert!(cache.find("abc").await.is_err());
+		assert!(cache.find("x").await.is_err());
+
+		assert_eq!(
+			0,
+			cache.purge(&PurgeFilter::Key("ab".into())).await.unwrap()
+		);
+		assert_eq!(
+			1,
+			cache.purge(&PurgeFilter::Key("abc1".into())).await.unwrap()
+		);
+		assert_eq!(2, cache.entries().await.unwrap().len());
+
+		std::fs::remove_dir_all(directory).unwrap();
+	}
+}
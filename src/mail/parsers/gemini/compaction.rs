@@ -0,0 +1,298 @@
+use std::collections::HashSet;
+use std::env;
+
+use regex::Regex;
+use scraper::{Html, Node};
+
+use crate::ErrorInterface;
+
+use super::eligibility::{AMOUNT_PATTERN, DATE_PATTERN};
+
+const DEFAULT_TOKEN_BUDGET: usize = 2000;
+
+/// How many lines before and after an amount or a date are kept when truncating.
+const CONTEXT_LINES: usize = 2;
+
+const BLOCK_ELEMENTS: &[&str] = &[
+	"br", "p", "div", "tr", "td", "th", "li", "table", "h1", "h2", "h3", "h4", "h5", "h6",
+];
+const SKIPPED_ELEMENTS: &[&str] = &["head", "style", "script", "title"];
+
+const FOOTER_MARKERS: &[&str] = &[
+	"このメールは送信専用",
+	"本メールは送信専用",
+	"配信停止",
+	"メールマガジンの配信",
+	"copyright",
+	"all rights reserved",
+	"unsubscribe",
+	"do not reply to this email",
+	"jangan membalas email ini",
+	"terdaftar dan diawasi oleh",
+];
+
+const TRACKING_HOST_KEYWORDS: &[&str] = &["click", "track", "r.", "email.", "link."];
+
+/// Shrinks mail bodies before they are sent to Gemini: strips markup and tracking links, drops
+/// repeated blocks and footers, and cuts the rest down to a token budget.
+pub struct Compactor {
+	pub token_budget: usize,
+	url_regex: Regex,
+	amount_regex: Regex,
+	date_regex: Regex,
+}
+
+impl Compactor {
+	pub fn new(token_budget: usize) -> Result<Self, ErrorInterface> {
+		Ok(Self {
+			token_budget,
+			url_regex: Regex::new(r"https?://([^/\s?#]+)[^\s<>]*")?,
+			amount_regex: Regex::new(AMOUNT_PATTERN)?,
+			date_regex: Regex::new(DATE_PATTERN)?,
+		})
+	}
+
+	/// Builds the compactor, reading the budget from `GEMINI_TOKEN_BUDGET`.
+	pub fn from_env() -> Result<Self, ErrorInterface> {
+		let token_budget = match env::var("GEMINI_TOKEN_BUDGET") {
+			Ok(budget) if !budget.is_empty() => budget.parse::<usize>()?,
+			_ => DEFAULT_TOKEN_BUDGET,
+		};
+
+		Self::new(token_budget)
+	}
+
+	pub fn compact(&self, body: &str) -> String {
+		let text = match looks_like_html(body) {
+			true => html_to_text(body),
+			false => body.to_owned(),
+		};
+		let text = self.strip_urls(&text);
+
+		let lines = text
+			.lines()
+			.map(|l| l.split_whitespace().collect::<Vec<&str>>().join(" "))
+			.collect::<Vec<String>>();
+		let lines = self.remove_repeated_blocks(lines);
+		let lines = self.remove_footers(lines);
+		let lines = self.truncate_to_budget(lines);
+
+		lines.join("\n")
+	}
+
+	fn strip_urls(&self, text: &str) -> String {
+		self.url_regex
+			.replace_all(text, |captures: &regex::Captures| {
+				let url = &captures[0];
+				let host = captures[1].to_lowercase();
+				let is_tracking = url.contains('?')
+					|| TRACKING_HOST_KEYWORDS.iter().any(|k| host.starts_with(k))
+					|| host.contains("track");
+				match is_tracking {
+					true => String::new(),
+					false => host,
+				}
+			})
+			.into_owned()
+	}
+
+	/// Drops blocks (runs of lines between empty lines) that were already seen earlier, unless
+	/// they carry an amount: the same purchase can legitimately appear twice.
+	fn remove_repeated_blocks(&self, lines: Vec<String>) -> Vec<String> {
+		let mut seen = HashSet::new();
+		let mut result = vec![];
+
+		for block in lines.split(|l| l.is_empty()) {
+			if block.is_empty() {
+				continue;
+			}
+
+			let key = block.join("\n");
+			if !self.amount_regex.is_match(&key) && !seen.insert(key) {
+				continue;
+			}
+
+			result.extend(block.iter().cloned());
+			result.push(String::new());
+		}
+		result.pop();
+
+		result
+	}
+
+	/// Cuts the mail at the first footer line that has no amounts after it.
+	fn remove_footers(&self, lines: Vec<String>) -> Vec<String> {
+		let is_footer = |line: &String| {
+			let line = line.to_lowercase();
+			FOOTER_MARKERS.iter().any(|m| line.contains(m))
+		};
+
+		let mut result = vec![];
+		for (i, line) in lines.iter().enumerate() {
+			if !is_footer(line) {
+				result.push(line.clone());
+				continue;
+			}
+
+			if !lines[i..].iter().any(|l| self.amount_regex.is_match(l)) {
+				break;
+			}
+		}
+
+		while result.last().is_some_and(|l| l.is_empty()) {
+			result.pop();
+		}
+
+		result
+	}
+
+	/// Keeps the lines around amounts and dates first, then fills what is left of the budget
+	/// with the other lines from the top.
+	fn truncate_to_budget(&self, lines: Vec<String>) -> Vec<String> {
+		if estimate_tokens(&lines.join("\n")) <= self.token_budget {
+			return lines;
+		}
+
+		let mut keep = vec![false; lines.len()];
+		let mut used = 0;
+
+		let important = lines
+			.iter()
+			.enumerate()
+			.filter(|(_, l)| self.amount_regex.is_match(l) || self.date_regex.is_match(l))
+			.map(|(i, _)| i)
+			.collect::<Vec<usize>>();
+		let around_important = important.iter().flat_map(|i| {
+			let start = i.saturating_sub(CONTEXT_LINES);
+			let end = (i + CONTEXT_LINES).min(lines.len() - 1);
+			(start..=end).collect::<Vec<usize>>()
+		});
+
+		for i in important
+			.iter()
+			.copied()
+			.chain(around_important)
+			.chain(0..lines.len())
+		{
+			if keep[i] {
+				continue;
+			}
+			let tokens = estimate_tokens(&lines[i]) + 1;
+			if used + tokens > self.token_budget {
+				continue;
+			}
+			keep[i] = true;
+			used += tokens;
+		}
+
+		lines
+			.into_iter()
+			.zip(keep)
+			.filter(|(_, keep)| *keep)
+			.map(|(line, _)| line)
+			.collect()
+	}
+}
+
+/// A rough token count: about four ASCII characters make a token, while Japanese text is closer
+/// to one token per character.
+pub fn estimate_tokens(text: &str) -> usize {
+	let ascii = text.chars().filter(|c| c.is_ascii()).count();
+	let other = text.chars().count() - ascii;
+	ascii.div_ceil(4) + other
+}
+
+fn looks_like_html(body: &str) -> bool {
+	let lowercase = body.to_lowercase();
+	["<html", "<body", "<table", "<div", "<br"]
+		.iter()
+		.any(|t| lowercase.contains(t))
+}
+
+fn html_to_text(body: &str) -> String {
+	let html = Html::parse_document(body);
+	let mut text = String::new();
+
+	for node in html.root_element().descendants() {
+		match node.value() {
+			Node::Element(element) if BLOCK_ELEMENTS.contains(&element.name()) => {
+				text.push('\n');
+			}
+			Node::Text(node_text) => {
+				let skipped = node.ancestors().any(|a| {
+					a.value()
+						.as_element()
+						.is_some_and(|e| SKIPPED_ELEMENTS.contains(&e.name()))
+				});
+				if !skipped {
+					text.push_str(node_text);
+				}
+			}
+			_ => {}
+		}
+	}
+
+	text
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::mail::Mail;
+	use crate::mail::parsers::{EmailParsingScheme, rakuten_card::RakutenCardParsingScheme};
+
+	use super::{Compactor, estimate_tokens};
+
+	#[test]
+	fn strips_markup_tracking_links_and_footers() {
+		let compactor = Compactor::new(2000).unwrap();
+		let body = include_str!("../fixtures/rakuten_pay.txt");
+		let compacted = compactor.compact(body);
+
+		assert!(compacted.len() < body.len());
+		assert!(!compacted.contains("<td>"));
+		assert!(!compacted.contains("font-family"));
+		assert!(!compacted.contains("scid="));
+		assert!(!compacted.contains("Copyright"));
+		assert!(!compacted.contains("送信専用"));
+		assert!(compacted.contains("ご利用店舗 ファミリーマート 西神中央店"));
+		assert!(compacted.contains("決済総額 1,234円"));
+		assert!(compacted.contains("2025/01/10(金) 18:42"));
+	}
+
+	#[test]
+	fn keeps_lines_around_amounts_when_over_budget() {
+		let compactor = Compactor::new(60).unwrap();
+		let mut body = String::new();
+		for i in 0..50 {
+			body.push_str(&format!(
+				"Some legal text that nobody reads, paragraph {}\n",
+				i
+			));
+		}
+		body.push_str("Merchant: TOKOPEDIA\nAmount: IDR 150,000.00\nDate: 05 Jan 2025 12:34\n");
+		for i in 0..50 {
+			body.push_str(&format!(
+				"More legal text that nobody reads, paragraph {}\n",
+				i
+			));
+		}
+
+		let compacted = compactor.compact(&body);
+		assert!(estimate_tokens(&compacted) <= 60);
+		assert!(compacted.contains("Merchant: TOKOPEDIA"));
+		assert!(compacted.contains("Amount: IDR 150,000.00"));
+		assert!(compacted.contains("Date: 05 Jan 2025 12:34"));
+	}
+
+	#[tokio::test]
+	async fn compacted_mail_still_parses() {
+		let compactor = Compactor::new(2000).unwrap();
+
+		let mut mail = Mail::create_test_mail();
+		mail.body = compactor.compact(include_str!("../fixtures/rakuten_card.txt"));
+		let scheme = RakutenCardParsingScheme {
+			account: "Rakuten".into(),
+		};
+		assert_eq!(2, scheme.parse(&mail).await.unwrap().len());
+	}
+}
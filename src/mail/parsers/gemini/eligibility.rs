@@ -0,0 +1,311 @@
+use std::env;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use log::info;
+use regex::Regex;
+
+use crate::ErrorInterface;
+use crate::mail::Mail;
+
+const DEFAULT_THRESHOLD: i32 = 3;
+
+pub(super) const AMOUNT_PATTERN: &str =
+	r"(?i)([0-9][0-9,.]*\s*円|[¥￥$]\s*[0-9]|\b(IDR|Rp\.?|JPY|USD|EUR|SGD)\s*[0-9])";
+pub(super) const DATE_PATTERN: &str = r"([0-9]{4}[/\-年][0-9]{1,2}[/\-月][0-9]{1,2}|[0-9]{1,2} [A-Za-z]{3} [0-9]{4}|[0-9]{1,2}:[0-9]{2})";
+
+const TRANSACTION_KEYWORDS: &[&str] = &[
+	"利用",
+	"決済",
+	"支払",
+	"引落",
+	"購入",
+	"領収",
+	"振込",
+	"payment",
+	"purchase",
+	"transaction",
+	"transfer",
+	"receipt",
+	"pembayaran",
+	"transaksi",
+];
+
+const NON_TRANSACTION_KEYWORDS: &[&str] = &[
+	"配信停止",
+	"メールマガジン",
+	"メルマガ",
+	"キャンペーン",
+	"発送",
+	"お届け",
+	"unsubscribe",
+	"newsletter",
+	"shipped",
+	"shipping",
+	"delivery",
+	"promo",
+];
+
+#[derive(Debug, Clone)]
+pub struct EligibilityDecision {
+	pub file_path: PathBuf,
+	pub mail_from: String,
+	pub mail_subject: String,
+	pub eligible: bool,
+	pub score: i32,
+	pub reasons: Vec<String>,
+}
+
+/// Decides which mails are worth sending to Gemini, using sender lists first and a keyword
+/// score for everything else.
+pub struct EligibilityFilter {
+	pub allowed_senders: Vec<String>,
+	pub denied_senders: Vec<String>,
+	pub keywords: Vec<String>,
+	pub negative_keywords: Vec<String>,
+	pub threshold: i32,
+	amount_regex: Regex,
+	date_regex: Regex,
+	decisions: Mutex<Vec<EligibilityDecision>>,
+}
+
+impl EligibilityFilter {
+	pub fn new(
+		allowed_senders: Vec<String>,
+		denied_senders: Vec<String>,
+		keywords: Vec<String>,
+		negative_keywords: Vec<String>,
+		threshold: i32,
+	) -> Result<Self, ErrorInterface> {
+		Ok(Self {
+			allowed_senders,
+			denied_senders,
+			keywords,
+			negative_keywords,
+			threshold,
+			amount_regex: Regex::new(AMOUNT_PATTERN)?,
+			date_regex: Regex::new(DATE_PATTERN)?,
+			decisions: Mutex::new(vec![]),
+		})
+	}
+
+	/// Builds the filter from the `GEMINI_ELIGIBILITY_*` variables. The filter is disabled
+	/// unless `GEMINI_ELIGIBILITY_ENABLED` is set to `true`.
+	pub fn from_env() -> Result<Option<Self>, ErrorInterface> {
+		if env::var("GEMINI_ELIGIBILITY_ENABLED").unwrap_or_default() != "true" {
+			return Ok(None);
+		}
+
+		let read_list = |key: &str| -> Vec<String> {
+			env::var(key)
+				.unwrap_or_default()
+				.split(",")
+				.map(|s| s.trim().to_lowercase())
+				.filter(|s| !s.is_empty())
+				.collect()
+		};
+
+		let threshold = match env::var("GEMINI_ELIGIBILITY_THRESHOLD") {
+			Ok(threshold) if !threshold.is_empty() => threshold.parse::<i32>()?,
+			_ => DEFAULT_THRESHOLD,
+		};
+
+		Ok(Some(Self::new(
+			read_list("GEMINI_ELIGIBILITY_ALLOWED_SENDERS"),
+			read_list("GEMINI_ELIGIBILITY_DENIED_SENDERS"),
+			read_list("GEMINI_ELIGIBILITY_KEYWORDS"),
+			read_list("GEMINI_ELIGIBILITY_NEGATIVE_KEYWORDS"),
+			threshold,
+		)?))
+	}
+
+	pub fn evaluate(&self, mail: &Mail) -> EligibilityDecision {
+		let from = mail.from.to_lowercase();
+		let mut decision = EligibilityDecision {
+			file_path: mail.file_path.clone(),
+			mail_from: mail.from.clone(),
+			mail_subject: mail.subject.clone(),
+			eligible: false,
+			score: 0,
+			reasons: vec![],
+		};
+
+		if let Some(sender) = self
+			.denied_senders
+			.iter()
+			.find(|s| from.contains(s.as_str()))
+		{
+			decision
+				.reasons
+				.push(format!("sender matches denylist '{}'", sender));
+			return decision;
+		}
+		if let Some(sender) = self
+			.allowed_senders
+			.iter()
+			.find(|s| from.contains(s.as_str()))
+		{
+			decision.eligible = true;
+			decision
+				.reasons
+				.push(format!("sender matches allowlist '{}'", sender));
+			return decision;
+		}
+
+		let subject = mail.subject.to_lowercase();
+		let body = mail.body.to_lowercase();
+
+		if self.amount_regex.is_match(&subject) || self.amount_regex.is_match(&body) {
+			decision.score += 3;
+			decision.reasons.push("has an amount (+3)".into());
+		}
+		if self.date_regex.is_match(&body) {
+			decision.score += 1;
+			decision.reasons.push("has a date or time (+1)".into());
+		}
+
+		let keywords = TRANSACTION_KEYWORDS
+			.iter()
+			.map(|k| k.to_string())
+			.chain(self.keywords.iter().cloned());
+		for keyword in keywords {
+			if subject.contains(&keyword) {
+				decision.score += 2;
+				decision
+					.reasons
+					.push(format!("subject has '{}' (+2)", keyword));
+			} else if body.contains(&keyword) {
+				decision.score += 1;
+				decision
+					.reasons
+					.push(format!("body has '{}' (+1)", keyword));
+			}
+		}
+
+		let negative_keywords = NON_TRANSACTION_KEYWORDS
+			.iter()
+			.map(|k| k.to_string())
+			.chain(self.negative_keywords.iter().cloned());
+		for keyword in negative_keywords {
+			if subject.contains(&keyword) || body.contains(&keyword) {
+				decision.score -= 2;
+				decision.reasons.push(format!("has '{}' (-2)", keyword));
+			}
+		}
+
+		decision.eligible = decision.score >= self.threshold;
+		decision
+	}
+
+	/// Evaluates the mail and keeps the decision for the report.
+	pub fn check(&self, mail: &Mail) -> bool {
+		let decision = self.evaluate(mail);
+		let eligible = decision.eligible;
+
+		if !eligible {
+			info!(
+				"Mail: [{}]. Not eligible for Gemini (score {}, threshold {}): {}",
+				mail.subject,
+				decision.score,
+				self.threshold,
+				decision.reasons.join(", ")
+			);
+		}
+
+		let mut decisions = self.decisions.lock().unwrap();
+		decisions.retain(|d| d.file_path != decision.file_path);
+		decisions.push(decision);
+
+		eligible
+	}
+
+	pub fn report(&self) -> Vec<EligibilityDecision> {
+		self.decisions.lock().unwrap().clone()
+	}
+
+	pub fn log_report(&self) {
+		let report = self.report();
+		let filtered = report.iter().filter(|d| !d.eligible).collect::<Vec<_>>();
+
+		info!(
+			"Gemini eligibility: {} mails checked, {} eligible, {} filtered",
+			report.len(),
+			report.len() - filtered.len(),
+			filtered.len()
+		);
+		for decision in filtered {
+			info!(
+				"Filtered: [{}] from [{}], score {}: {}",
+				decision.mail_subject,
+				decision.mail_from,
+				decision.score,
+				decision.reasons.join(", ")
+			);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::mail::Mail;
+
+	use super::EligibilityFilter;
+
+	fn make_filter() -> EligibilityFilter {
+		EligibilityFilter::new(
+			vec!["@bank.example".into()],
+			vec!["@shop-news.example".into()],
+			vec![],
+			vec![],
+			3,
+		)
+		.unwrap()
+	}
+
+	fn make_mail(from: &str, subject: &str, body: &str) -> Mail {
+		let mut mail = Mail::create_test_mail();
+		mail.from = from.into();
+		mail.subject = subject.into();
+		mail.body = body.into();
+		mail
+	}
+
+	#[test]
+	fn sender_lists_take_precedence_over_scoring() {
+		let filter = make_filter();
+
+		let mail = make_mail("Bank <info@bank.example>", "Hello", "Nothing here");
+		assert!(filter.evaluate(&mail).eligible);
+
+		let mail = make_mail(
+			"Shop <news@shop-news.example>",
+			"Payment received",
+			"Amount: 1,000 円",
+		);
+		assert!(!filter.evaluate(&mail).eligible);
+	}
+
+	#[test]
+	fn scores_transaction_like_mails_above_newsletters() {
+		let filter = make_filter();
+
+		let mail = make_mail(
+			"store@example.com",
+			"ご利用のお知らせ",
+			"2025/01/02 12:34 ご利用金額 1,200円",
+		);
+		assert!(filter.check(&mail));
+
+		let mut mail = make_mail(
+			"store@example.com",
+			"今週のキャンペーン",
+			"セール開催中！配信停止はこちら",
+		);
+		mail.file_path = "/tmp/fake-path-2".into();
+		assert!(!filter.check(&mail));
+
+		let report = filter.report();
+		assert_eq!(2, report.len());
+		assert_eq!(1, report.iter().filter(|d| !d.eligible).count());
+	}
+}
@@ -0,0 +1,219 @@
+use std::env;
+
+use regex::{Captures, Regex};
+
+use crate::ErrorInterface;
+
+const EMAIL_PATTERN: &str = r"[A-Za-z0-9._%+\-]+@[A-Za-z0-9.\-]+\.[A-Za-z]{2,}";
+const CARD_PATTERN: &str = r"\b[0-9*Xx]{4}[ \-]?[0-9*Xx]{4}[ \-]?[0-9*Xx]{4}[ \-]?[0-9*Xx]{1,4}\b";
+const MASKED_NUMBER_PATTERN: &str = r"[*Xx]{2,}[ \-]?[0-9]{2,}";
+const PHONE_PATTERN: &str =
+	r"(\+[0-9]{1,3}[ \-]?[0-9]{1,4}([ \-]?[0-9]{2,4}){2,3}|\b0[0-9]{1,4}-[0-9]{1,4}-[0-9]{3,4}\b)";
+const LONG_NUMBER_PATTERN: &str = r"\b[0-9][0-9 \-]{5,}[0-9]\b";
+/// yyyy-mm-dd and dd-mm-yyyy dates with an optional time, which the number patterns would take for
+/// phone or account numbers.
+const DASHED_DATE_PATTERN: &str = r"\b((19|20)[0-9]{2}-(0[1-9]|1[0-2])-(0[1-9]|[12][0-9]|3[01])|(0[1-9]|[12][0-9]|3[01])-(0[1-9]|1[0-2])-(19|20)[0-9]{2})([ T][0-9]{1,2}:[0-9]{2}(:[0-9]{2})?)?\b";
+
+/// Masks card and account numbers, phone numbers, email addresses and configured personal names
+/// before mail contents leave the machine. Amounts, dates and merchant names are kept as they are.
+pub struct Redactor {
+	/// The configured personal names, as given.
+	pub names: Vec<String>,
+	email_regex: Regex,
+	card_regex: Regex,
+	masked_number_regex: Regex,
+	phone_regex: Regex,
+	long_number_regex: Regex,
+	dashed_date_regex: Regex,
+	name_regexes: Vec<Regex>,
+}
+
+impl Redactor {
+	pub fn new(names: &[String]) -> Result<Self, ErrorInterface> {
+		let mut name_regexes = vec![];
+		for name in names {
+			let parts = name
+				.split(|c: char| c.is_whitespace())
+				.filter(|p| !p.is_empty())
+				.map(regex::escape)
+				.collect::<Vec<String>>();
+			if parts.is_empty() {
+				continue;
+			}
+			// allow any kind or amount of whitespace between the parts of a name
+			name_regexes.push(Regex::new(&format!("(?i){}", parts.join(r"\s*")))?);
+		}
+
+		Ok(Self {
+			names: names.to_vec(),
+			email_regex: Regex::new(EMAIL_PATTERN)?,
+			card_regex: Regex::new(CARD_PATTERN)?,
+			masked_number_regex: Regex::new(MASKED_NUMBER_PATTERN)?,
+			phone_regex: Regex::new(PHONE_PATTERN)?,
+			long_number_regex: Regex::new(LONG_NUMBER_PATTERN)?,
+			dashed_date_regex: Regex::new(DASHED_DATE_PATTERN)?,
+			name_regexes,
+		})
+	}
+
+	/// Builds the redactor, reading the names to mask from `GEMINI_REDACTED_NAMES`.
+	pub fn from_env() -> Result<Self, ErrorInterface> {
+		let names = env::var("GEMINI_REDACTED_NAMES")
+			.unwrap_or_default()
+			.split(",")
+			.map(|s| s.trim().to_owned())
+			.filter(|s| !s.is_empty())
+			.collect::<Vec<String>>();
+
+		Self::new(&names)
+	}
+
+	pub fn redact(&self, text: &str) -> String {
+		let mut text = self.email_regex.replace_all(text, "[EMAIL]").into_owned();
+
+		for name_regex in &self.name_regexes {
+			text = name_regex.replace_all(&text, "[NAME]").into_owned();
+		}
+
+		text = self
+			.card_regex
+			.replace_all(&text, |captures: &Captures| {
+				let matched = &captures[0];
+				// a card number has to have some digits, don't touch runs of asterisks
+				match matched.chars().filter(|c| c.is_ascii_digit()).count() >= 4 {
+					true => "[CARD]".to_owned(),
+					false => matched.to_owned(),
+				}
+			})
+			.into_owned();
+		text = self
+			.masked_number_regex
+			.replace_all(&text, "[ACCOUNT]")
+			.into_owned();
+
+		// dates are kept out of the phone and account number patterns
+		let mut redacted = String::new();
+		let mut last_end = 0;
+		for date in self.dashed_date_regex.find_iter(&text) {
+			redacted.push_str(&self.redact_numbers(&text[last_end..date.start()]));
+			redacted.push_str(date.as_str());
+			last_end = date.end();
+		}
+		redacted.push_str(&self.redact_numbers(&text[last_end..]));
+
+		redacted
+	}
+
+	fn redact_numbers(&self, text: &str) -> String {
+		let text = self.phone_regex.replace_all(text, "[PHONE]").into_owned();
+
+		self.long_number_regex
+			.replace_all(&text, |captures: &Captures| {
+				let matched = captures.get(0).unwrap();
+				let digits = matched
+					.as_str()
+					.chars()
+					.filter(|c| c.is_ascii_digit())
+					.count();
+				if digits < 7 || is_amount(&text, matched.start(), matched.end()) {
+					return matched.as_str().to_owned();
+				}
+				"[ACCOUNT]".to_owned()
+			})
+			.into_owned()
+	}
+}
+
+/// Whether the number at the given range is written next to a currency, in which case it is an
+/// amount rather than an account number.
+fn is_amount(text: &str, start: usize, end: usize) -> bool {
+	let before = text[..start].trim_end().to_uppercase();
+	let after = text[end..].trim_start();
+
+	["IDR", "RP", "RP.", "JPY", "USD", "¥", "￥", "$"]
+		.iter()
+		.any(|c| before.ends_with(c))
+		|| ["円", "IDR", "JPY", "USD"]
+			.iter()
+			.any(|c| after.starts_with(c))
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::mail::Mail;
+	use crate::mail::parsers::{
+		EmailParsingScheme, ocbc::OcbcPaymentNotificationScheme,
+		rakuten_card::RakutenCardParsingScheme,
+	};
+
+	use super::Redactor;
+
+	fn make_redactor() -> Redactor {
+		Redactor::new(&["楽天 太郎".to_owned(), "Rahmat Hidayat".to_owned()]).unwrap()
+	}
+
+	#[test]
+	fn masks_personal_data_but_keeps_amounts_and_merchants() {
+		let redactor = make_redactor();
+		let redacted = redactor.redact(include_str!("../fixtures/rakuten_card.txt"));
+
+		assert!(!redacted.contains("楽天 太郎"));
+		assert!(!redacted.contains("1234-56**-****-7890"));
+		assert!(!redacted.contains("taro.rakuten@example.com"));
+		assert!(!redacted.contains("0570-66-6910"));
+		assert!(redacted.contains("[NAME] 様"));
+		assert!(redacted.contains("■カード番号: [CARD]"));
+
+		assert!(redacted.contains("■利用先: セブン-イレブン 西神中央駅前"));
+		assert!(redacted.contains("■利用金額: 4,980 円"));
+		assert!(redacted.contains("■利用日: 2025/01/06"));
+
+		let redacted = redactor.redact(include_str!("../fixtures/ocbc_payment.html"));
+		assert!(!redacted.contains("RAHMAT HIDAYAT"));
+		assert!(!redacted.contains("693810123456"));
+		assert!(!redacted.contains("+62 21 2650 6300"));
+		assert!(!redacted.contains("tanya@ocbc.id"));
+		assert!(redacted.contains("IDR 150,000.00"));
+		assert!(redacted.contains("05 Jan 2025 12:34:56 WIB"));
+		assert!(redacted.contains("TOKOPEDIA"));
+
+		assert_eq!("IDR 1500000", redactor.redact("IDR 1500000"));
+		assert_eq!("1500000円", redactor.redact("1500000円"));
+
+		let redacted = redactor
+			.redact("Tanggal: 2025-01-06 12:34\nTanggal: 05-01-2025 10:15\nRekening: 693810123456");
+		assert!(redacted.contains("Tanggal: 2025-01-06 12:34\n"));
+		assert!(redacted.contains("Tanggal: 05-01-2025 10:15\n"));
+		assert!(redacted.contains("Rekening: [ACCOUNT]"));
+	}
+
+	#[tokio::test]
+	async fn redacted_mails_still_parse_to_the_same_transactions() {
+		let redactor = make_redactor();
+
+		let mut mail = Mail::create_test_mail();
+		mail.from = "info@mail.rakuten-card.co.jp".into();
+		mail.subject = "カード利用のお知らせ".into();
+		mail.body = include_str!("../fixtures/rakuten_card.txt").into();
+		let scheme = RakutenCardParsingScheme {
+			account: "Rakuten".into(),
+		};
+		let original = scheme.parse(&mail).await.unwrap();
+		mail.body = redactor.redact(&mail.body);
+		let redacted = scheme.parse(&mail).await.unwrap();
+		assert_eq!(2, redacted.len());
+		assert_eq!(format!("{:?}", original), format!("{:?}", redacted));
+
+		let mut mail = Mail::create_test_mail();
+		mail.from = "Notifikasi OCBC <notifikasi@ocbc.id>".into();
+		mail.subject = "Successful Payment to TOKOPEDIA".into();
+		mail.body = include_str!("../fixtures/ocbc_payment.html").into();
+		let scheme = OcbcPaymentNotificationScheme {
+			account: "OCBC".into(),
+		};
+		let original = scheme.parse(&mail).await.unwrap();
+		mail.body = redactor.redact(&mail.body);
+		let redacted = scheme.parse(&mail).await.unwrap();
+		assert_eq!(format!("{:?}", original), format!("{:?}", redacted));
+	}
+}
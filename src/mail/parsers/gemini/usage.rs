@@ -0,0 +1,519 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use log::warn;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::ErrorInterface;
+
+/// The `usageMetadata` object of a Gemini response.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageMetadata {
+	#[serde(default)]
+	pub prompt_token_count: u64,
+	#[serde(default)]
+	pub candidates_token_count: u64,
+	#[serde(default)]
+	pub thoughts_token_count: u64,
+	#[serde(default)]
+	pub total_token_count: u64,
+}
+
+/// One line of the usage ledger, written for every mail sent to Gemini.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UsageRecord {
+	pub timestamp: DateTime<Utc>,
+	pub model: String,
+	pub mail_file: String,
+	pub mail_subject: String,
+	pub input_tokens: u64,
+	pub output_tokens: u64,
+}
+
+impl UsageRecord {
+	pub fn new(model: &str, mail_file: &str, mail_subject: &str, usage: &UsageMetadata) -> Self {
+		Self {
+			timestamp: Utc::now(),
+			model: model.to_owned(),
+			mail_file: mail_file.to_owned(),
+			mail_subject: mail_subject.to_owned(),
+			input_tokens: usage.prompt_token_count,
+			// thinking tokens are billed as output
+			output_tokens: usage.candidates_token_count + usage.thoughts_token_count,
+		}
+	}
+
+	/// Splits the usage of a request with several mails into one record per mail, each taking a
+	/// share of the tokens by its weight, e.g. the length of its body.
+	pub fn split(model: &str, mails: &[(&str, &str, usize)], usage: &UsageMetadata) -> Vec<Self> {
+		let whole = Self::new(model, "", "", usage);
+		let total_weight = mails.iter().map(|(_, _, w)| *w as u64).sum::<u64>().max(1);
+		let share = |tokens: u64, weight: usize| tokens * weight as u64 / total_weight;
+
+		let mut records = mails
+			.iter()
+			.map(|(mail_file, mail_subject, weight)| Self {
+				mail_file: mail_file.to_string(),
+				mail_subject: mail_subject.to_string(),
+				input_tokens: share(whole.input_tokens, *weight),
+				output_tokens: share(whole.output_tokens, *weight),
+				..whole.clone()
+			})
+			.collect::<Vec<Self>>();
+		// rounding leftovers go to the last mail so the records add up to the request
+		let input_tokens = records.iter().map(|r| r.input_tokens).sum::<u64>();
+		let output_tokens = records.iter().map(|r| r.output_tokens).sum::<u64>();
+		if let Some(last) = records.last_mut() {
+			last.input_tokens += whole.input_tokens - input_tokens;
+			last.output_tokens += whole.output_tokens - output_tokens;
+		}
+
+		records
+	}
+}
+
+/// Prices in USD per one million tokens.
+#[derive(Debug, Clone)]
+pub struct ModelPrice {
+	pub input: Decimal,
+	pub output: Decimal,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct DailyUsage {
+	pub calls: u64,
+	pub input_tokens: u64,
+	pub output_tokens: u64,
+	pub cost: Decimal,
+}
+
+/// What was spent on one UTC day, kept while running so the ledger is read only once.
+#[derive(Debug, Default, Clone)]
+struct DaySpending {
+	cost: Decimal,
+	/// A model used that day without a price, whose cost can't be known.
+	unpriced_model: Option<String>,
+}
+
+pub struct UsageLedger {
+	pub path: PathBuf,
+	pub prices: HashMap<String, ModelPrice>,
+	pub daily_budget: Option<Decimal>,
+	pub monthly_budget: Option<Decimal>,
+	/// Spending per day, read from the ledger on the first budget check and added to as usage
+	/// is recorded.
+	spending: Mutex<Option<BTreeMap<NaiveDate, DaySpending>>>,
+}
+
+impl UsageLedger {
+	/// Builds the ledger from `GEMINI_USAGE_FILE`, `GEMINI_PRICE_TABLE_FILE`,
+	/// `GEMINI_DAILY_BUDGET` and `GEMINI_MONTHLY_BUDGET`. Usage is not tracked if the usage file
+	/// is not set.
+	pub fn from_env() -> Result<Option<Self>, ErrorInterface> {
+		let path = match env::var("GEMINI_USAGE_FILE") {
+			Ok(path) if !path.is_empty() => PathBuf::from(path),
+			_ => return Ok(None),
+		};
+
+		let prices = match env::var("GEMINI_PRICE_TABLE_FILE") {
+			Ok(price_table_path) if !price_table_path.is_empty() => {
+				read_price_table(&PathBuf::from(price_table_path))?
+			}
+			_ => HashMap::new(),
+		};
+
+		let read_budget = |key: &str| -> Result<Option<Decimal>, ErrorInterface> {
+			match env::var(key) {
+				Ok(budget) if !budget.is_empty() => Ok(Some(Decimal::from_str(&budget)?)),
+				_ => Ok(None),
+			}
+		};
+
+		Ok(Some(Self::new(
+			path,
+			prices,
+			read_budget("GEMINI_DAILY_BUDGET")?,
+			read_budget("GEMINI_MONTHLY_BUDGET")?,
+		)))
+	}
+
+	pub fn new(
+		path: PathBuf,
+		prices: HashMap<String, ModelPrice>,
+		daily_budget: Option<Decimal>,
+		monthly_budget: Option<Decimal>,
+	) -> Self {
+		Self {
+			path,
+			prices,
+			daily_budget,
+			monthly_budget,
+			spending: Mutex::new(None),
+		}
+	}
+
+	pub fn record(&self, record: &UsageRecord) -> Result<(), ErrorInterface> {
+		if let Some(parent) = self.path.parent() {
+			fs::create_dir_all(parent)?;
+		}
+
+		let mut file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(&self.path)?;
+		writeln!(file, "{}", serde_json::to_string(record)?)?;
+
+		if let Some(spending) = self.spending.lock().unwrap().as_mut() {
+			self.add_spending(spending, record);
+		}
+
+		Ok(())
+	}
+
+	fn add_spending(&self, spending: &mut BTreeMap<NaiveDate, DaySpending>, record: &UsageRecord) {
+		let day = spending.entry(record.timestamp.date_naive()).or_default();
+		match self.cost(record) {
+			Some(cost) => day.cost += cost,
+			None => day.unpriced_model = Some(record.model.clone()),
+		}
+	}
+
+	pub fn records(&self) -> Result<Vec<UsageRecord>, ErrorInterface> {
+		if !self.path.exists() {
+			return Ok(vec![]);
+		}
+
+		let reader = BufReader::new(File::open(&self.path)?);
+		let mut records = vec![];
+		for (line_num, line_result) in reader.lines().enumerate() {
+			let line = line_result?;
+			if line.trim().is_empty() {
+				continue;
+			}
+
+			match serde_json::from_str::<UsageRecord>(&line) {
+				Ok(record) => records.push(record),
+				Err(e) => warn!("Usage ledger line {} is unreadable: {}", line_num + 1, e),
+			}
+		}
+
+		Ok(records)
+	}
+
+	/// Finds the price for a model, falling back to the longest configured prefix so that
+	/// dated model versions share the price of their base model.
+	pub fn price_for(&self, model: &str) -> Option<&ModelPrice> {
+		if let Some(price) = self.prices.get(model) {
+			return Some(price);
+		}
+
+		self.prices
+			.iter()
+			.filter(|(name, _)| model.starts_with(name.as_str()))
+			.max_by_key(|(name, _)| name.len())
+			.map(|(_, price)| price)
+	}
+
+	/// Returns the cost of the call, or `None` if its model has no price.
+	pub fn cost(&self, record: &UsageRecord) -> Option<Decimal> {
+		let price = self.price_for(&record.model)?;
+
+		let million = Decimal::from(1_000_000);
+		Some(
+			(Decimal::from(record.input_tokens) * price.input
+				+ Decimal::from(record.output_tokens) * price.output)
+				/ million,
+		)
+	}
+
+	/// Sums up the records per UTC day and model. Calls to models without a price count as free.
+	pub fn daily_usage(
+		&self,
+		records: &[UsageRecord],
+	) -> BTreeMap<(NaiveDate, String), DailyUsage> {
+		let mut map: BTreeMap<(NaiveDate, String), DailyUsage> = BTreeMap::new();
+		let mut unpriced_models = HashSet::new();
+
+		for record in records {
+			let day = map
+				.entry((record.timestamp.date_naive(), record.model.clone()))
+				.or_default();
+			day.calls += 1;
+			day.input_tokens += record.input_tokens;
+			day.output_tokens += record.output_tokens;
+			match self.cost(record) {
+				Some(cost) => day.cost += cost,
+				None => {
+					if unpriced_models.insert(record.model.as_str()) {
+						warn!("No price for model {}, counting it as free", record.model);
+					}
+				}
+			}
+		}
+
+		map
+	}
+
+	/// Returns the reason if today's or this month's spending has reached its budget. Spending
+	/// can't be known for models without a price, so the budget counts as exceeded if one of the
+	/// given models about to be used, or one used this month, has no price.
+	pub fn budget_exceeded(&self, models: &[String]) -> Result<Option<String>, ErrorInterface> {
+		if self.daily_budget.is_none() && self.monthly_budget.is_none() {
+			return Ok(None);
+		}
+
+		if let Some(model) = models.iter().find(|m| self.price_for(m).is_none()) {
+			warn!(
+				"No price for model {}, treating the budget as exceeded",
+				model
+			);
+			return Ok(Some(format!("no price for model {}", model)));
+		}
+
+		let mut spending = self.spending.lock().unwrap();
+		if spending.is_none() {
+			let mut loaded = BTreeMap::new();
+			for record in self.records()? {
+				self.add_spending(&mut loaded, &record);
+			}
+			*spending = Some(loaded);
+		}
+
+		let today = Utc::now().date_naive();
+		let month_start = today.with_day(1).unwrap();
+		let mut spent_today = Decimal::ZERO;
+		let mut spent_this_month = Decimal::ZERO;
+		for (date, day) in spending.as_ref().unwrap().range(month_start..=today) {
+			if let Some(model) = &day.unpriced_model {
+				warn!(
+					"No price for model {}, treating the budget as exceeded",
+					model
+				);
+				return Ok(Some(format!("no price for model {}", model)));
+			}
+			spent_this_month += day.cost;
+			if *date == today {
+				spent_today += day.cost;
+			}
+		}
+
+		match self.daily_budget {
+			Some(budget) if spent_today >= budget => {
+				return Ok(Some(format!(
+					"spent ${} today, daily budget is ${}",
+					spent_today.round_dp(4),
+					budget
+				)));
+			}
+			_ => {}
+		}
+		match self.monthly_budget {
+			Some(budget) if spent_this_month >= budget => {
+				return Ok(Some(format!(
+					"spent ${} this month, monthly budget is ${}",
+					spent_this_month.round_dp(4),
+					budget
+				)));
+			}
+			_ => {}
+		}
+
+		Ok(None)
+	}
+}
+
+/// Reads a CSV file of `model,input price,output price` lines, prices being USD per one million
+/// tokens.
+fn read_price_table(path: &PathBuf) -> Result<HashMap<String, ModelPrice>, ErrorInterface> {
+	let reader = BufReader::new(File::open(path)?);
+	let mut map = HashMap::new();
+
+	for (line_num, line_result) in reader.lines().enumerate() {
+		let line = line_result?;
+		let trimmed_line = line.trim();
+
+		if trimmed_line.is_empty() {
+			continue;
+		}
+
+		let parts: Vec<&str> = trimmed_line.split(',').map(|p| p.trim()).collect();
+		let prices = match parts.as_slice() {
+			[_, input, output] => (Decimal::from_str(input), Decimal::from_str(output)),
+			_ => {
+				warn!(
+					"Price table line {} has unexpected number of items",
+					line_num + 1
+				);
+				continue;
+			}
+		};
+
+		match prices {
+			(Ok(input), Ok(output)) => {
+				map.insert(parts[0].to_string(), ModelPrice { input, output });
+			}
+			_ => warn!("Price table line {} has invalid prices", line_num + 1),
+		}
+	}
+
+	Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+
+	use chrono::Utc;
+	use rust_decimal::Decimal;
+
+	use super::{ModelPrice, UsageLedger, UsageMetadata, UsageRecord};
+
+	fn make_ledger(name: &str) -> UsageLedger {
+		let path = std::env::temp_dir().join(format!(
+			"negi-usage-test-{}-{}.jsonl",
+			name,
+			std::process::id()
+		));
+		let _ = std::fs::remove_file(&path);
+
+		let mut prices = HashMap::new();
+		prices.insert(
+			"gemini-2.5-flash".to_owned(),
+			ModelPrice {
+				input: Decimal::new(30, 2),
+				output: Decimal::new(250, 2),
+			},
+		);
+		prices.insert(
+			"gemini-2.5-flash-lite".to_owned(),
+			ModelPrice {
+				input: Decimal::new(10, 2),
+				output: Decimal::new(40, 2),
+			},
+		);
+
+		UsageLedger::new(path, prices, None, None)
+	}
+
+	fn make_record(model: &str, input_tokens: u64, output_tokens: u64) -> UsageRecord {
+		UsageRecord {
+			timestamp: Utc::now(),
+			model: model.into(),
+			mail_file: "/tmp/fake-path".into(),
+			mail_subject: "subject".into(),
+			input_tokens,
+			output_tokens,
+		}
+	}
+
+	#[test]
+	fn cost_uses_longest_matching_model_prefix() {
+		let ledger = make_ledger("prices");
+
+		let record = make_record("gemini-2.5-flash-lite-preview", 1_000_000, 1_000_000);
+		assert_eq!(Some(Decimal::new(50, 2)), ledger.cost(&record));
+
+		let record = make_record("gemini-2.5-flash", 2_000_000, 0);
+		assert_eq!(Some(Decimal::new(60, 2)), ledger.cost(&record));
+
+		let record = make_record("unknown-model", 2_000_000, 0);
+		assert_eq!(None, ledger.cost(&record));
+	}
+
+	#[test]
+	fn budget_is_exceeded_once_spending_reaches_it() {
+		let mut ledger = make_ledger("budget");
+		ledger.daily_budget = Some(Decimal::new(1, 0));
+
+		ledger
+			.record(&make_record("gemini-2.5-flash", 0, 200_000))
+			.unwrap();
+		let models = vec!["gemini-2.5-flash".to_owned()];
+		assert!(ledger.budget_exceeded(&models).unwrap().is_none());
+
+		ledger
+			.record(&make_record("gemini-2.5-flash", 0, 200_000))
+			.unwrap();
+		assert!(ledger.budget_exceeded(&models).unwrap().is_some());
+
+		let daily_usage = ledger.daily_usage(&ledger.records().unwrap());
+		assert_eq!(1, daily_usage.len());
+		assert_eq!(2, daily_usage.values().next().unwrap().calls);
+
+		std::fs::remove_file(&ledger.path).unwrap();
+	}
+
+	#[test]
+	fn unpriced_model_counts_as_over_budget() {
+		let mut ledger = make_ledger("unpriced");
+		ledger.monthly_budget = Some(Decimal::new(100, 0));
+
+		let models = vec!["gemini-2.5-flash".to_owned(), "unknown-model".to_owned()];
+		assert!(ledger.budget_exceeded(&models).unwrap().is_some());
+		assert!(ledger.budget_exceeded(&models[..1]).unwrap().is_none());
+
+		ledger
+			.record(&make_record("unknown-model", 10, 10))
+			.unwrap();
+		assert!(ledger.budget_exceeded(&models[..1]).unwrap().is_some());
+
+		std::fs::remove_file(&ledger.path).unwrap();
+	}
+
+	#[test]
+	fn batch_usage_is_split_per_mail() {
+		let usage = UsageMetadata {
+			prompt_token_count: 1000,
+			candidates_token_count: 100,
+			thoughts_token_count: 1,
+			..Default::default()
+		};
+		let records = UsageRecord::split(
+			"gemini-2.5-flash",
+			&[("a.eml", "A", 100), ("b.eml", "B", 200)],
+			&usage,
+		);
+
+		assert_eq!(
+			vec![("a.eml", 333, 33), ("b.eml", 667, 68)],
+			records
+				.iter()
+				.map(|r| (r.mail_file.as_str(), r.input_tokens, r.output_tokens))
+				.collect::<Vec<(&str, u64, u64)>>()
+		);
+	}
+
+	#[test]
+	fn ledger_is_read_once_and_kept_up_to_date() {
+		let mut ledger = make_ledger("running");
+		ledger.daily_budget = Some(Decimal::new(1, 0));
+		let models = vec!["gemini-2.5-flash".to_owned()];
+		assert!(ledger.budget_exceeded(&models).unwrap().is_none());
+
+		// written by someone else after the ledger was read
+		std::fs::write(
+			&ledger.path,
+			format!(
+				"{}\n",
+				serde_json::to_string(&make_record("gemini-2.5-flash", 0, 1_000_000)).unwrap()
+			),
+		)
+		.unwrap();
+		assert!(ledger.budget_exceeded(&models).unwrap().is_none());
+
+		ledger
+			.record(&make_record("gemini-2.5-flash", 0, 400_000))
+			.unwrap();
+		assert!(ledger.budget_exceeded(&models).unwrap().is_some());
+
+		std::fs::remove_file(&ledger.path).unwrap();
+	}
+}
@@ -0,0 +1,123 @@
+use std::collections::BTreeMap;
+
+use crate::ErrorInterface;
+use crate::mail::{
+	Mail, address_of,
+	parsers::{parse_idr_amount, parse_labeled_lines, parse_local_datetime},
+};
+
+use super::{EmailParsingScheme, Transaction};
+
+/// Transaction types that put money into the account. "Top up" is left out, since it is also
+/// used for topping up e-wallets from the account.
+const INCOMING_TYPES: &[&str] = &["incoming", "received", "refund"];
+
+/// Jenius sends its notifications from this domain only.
+const SENDER_DOMAIN: &str = "@jenius.com";
+
+pub struct JeniusNotificationScheme {
+	pub account: String,
+}
+
+#[async_trait::async_trait]
+impl EmailParsingScheme for JeniusNotificationScheme {
+	fn can_parse(&self, mail: &Mail) -> bool {
+		let subject = mail.subject.to_lowercase();
+		address_of(&mail.from).ends_with(SENDER_DOMAIN)
+			&& (subject.contains("transaction notification")
+				|| subject.contains("payment successful")
+				|| subject.contains("pembayaran berhasil"))
+	}
+
+	async fn parse(&self, mail: &Mail) -> Result<Vec<Transaction>, ErrorInterface> {
+		let fields = parse_labeled_lines(&mail.body);
+		let find_field = |labels: &[&str]| labels.iter().find_map(|l| fields.get(*l));
+
+		let kind = find_field(&["Transaction Type", "Jenis Transaksi"])
+			.cloned()
+			.unwrap_or("Payment".to_owned());
+		let incoming = INCOMING_TYPES
+			.iter()
+			.any(|t| kind.to_lowercase().contains(t));
+
+		// Amount
+		let amount_string =
+			find_field(&["Amount", "Total Amount", "Jumlah"]).ok_or("No amount data found")?;
+		let mut amount = parse_idr_amount(amount_string)?;
+		amount.set_sign_negative(!incoming);
+
+		// Datetime
+		let datetime_string =
+			find_field(&["Date & Time", "Date", "Tanggal"]).ok_or("No datetime data found")?;
+		let datetime = parse_local_datetime(
+			datetime_string,
+			&["%d %b %Y, %H:%M", "%d %b %Y %H:%M:%S", "%d %b %Y %H:%M"],
+			chrono_tz::Asia::Jakarta,
+		)?;
+
+		// Subject
+		let subject = find_field(&["Merchant", "Pay To", "Recipient", "From"])
+			.cloned()
+			.unwrap_or(kind.clone());
+
+		let mut metadata = BTreeMap::new();
+		metadata.insert("kind".to_owned(), kind);
+
+		Ok(vec![Transaction {
+			subject: Some(subject),
+			datetime,
+			amount,
+			account: self.account.clone(),
+			category: None,
+			receipt: None,
+			metadata,
+		}])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::mail::Mail;
+	use crate::mail::parsers::EmailParsingScheme;
+
+	use super::JeniusNotificationScheme;
+
+	#[tokio::test]
+	async fn parses_card_transactions_and_payments() {
+		let scheme = JeniusNotificationScheme {
+			account: "Jenius".into(),
+		};
+
+		let mut mail = Mail::create_test_mail();
+		mail.from = "Jenius <noreply@jenius.com>".into();
+		mail.subject = "Jenius Transaction Notification".into();
+		mail.body = include_str!("fixtures/jenius_card.txt").into();
+		assert!(scheme.can_parse(&mail));
+		mail.from = "Jenius <promo@jenius-rewards.example>".into();
+		assert!(!scheme.can_parse(&mail));
+		mail.from = "Jenius <noreply@jenius.com>".into();
+
+		let transactions = scheme.parse(&mail).await.unwrap();
+		assert_eq!(
+			Some("STARBUCKS PLAZA SENAYAN".to_owned()),
+			transactions[0].subject
+		);
+		assert_eq!("-65000", transactions[0].amount.to_string());
+		assert_eq!(
+			"2025-01-13 09:05:00 UTC",
+			transactions[0].datetime.to_string()
+		);
+
+		mail.subject = "Payment Successful".into();
+		mail.body = include_str!("fixtures/jenius_payment.txt").into();
+		assert!(scheme.can_parse(&mail));
+
+		let transactions = scheme.parse(&mail).await.unwrap();
+		assert_eq!(Some("PLN POSTPAID".to_owned()), transactions[0].subject);
+		assert_eq!("-1350250.75", transactions[0].amount.to_string());
+		assert_eq!(
+			"2025-01-14 03:11:12 UTC",
+			transactions[0].datetime.to_string()
+		);
+	}
+}
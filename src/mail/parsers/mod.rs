@@ -1,25 +1,45 @@
 use std::collections::HashMap;
 
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone, Utc};
 #[cfg(debug_assertions)]
 use log::debug;
 
 use log::{error, info};
 use regex::Regex;
+use rust_decimal::Decimal;
 
 use crate::ErrorInterface;
 use crate::transaction::Transaction;
 
 use super::{Mail, TransactionsParsedFromMail};
 
+pub mod amazon;
+pub mod bca;
+pub mod digital_store;
 pub mod gemini;
+pub mod jenius;
 pub mod ocbc;
+pub mod paypay;
 pub mod rakuten_card;
 pub mod rakuten_pay;
+pub mod utility_bill;
+pub mod yuucho;
 
 #[async_trait::async_trait]
-pub trait EmailParsingScheme {
+pub trait EmailParsingScheme: Sync {
 	fn can_parse(&self, mail: &Mail) -> bool;
 	async fn parse(&self, mail: &Mail) -> Result<Vec<Transaction>, ErrorInterface>;
+
+	/// Parses several mails, returning one result per mail in the same order. Schemes that can
+	/// handle mails together (e.g. in one request) override this.
+	async fn parse_many(&self, mails: &[&Mail]) -> Vec<Result<Vec<Transaction>, ErrorInterface>> {
+		let mut results = vec![];
+		for mail in mails {
+			results.push(self.parse(mail).await);
+		}
+
+		results
+	}
 }
 
 pub async fn parse_emails(
@@ -27,15 +47,47 @@ pub async fn parse_emails(
 	parsers: &Vec<Box<dyn EmailParsingScheme>>,
 ) -> Result<TransactionsParsedFromMail, ErrorInterface> {
 	let mut map = HashMap::new();
+	let mut unparsed_mails = mails;
 
-	for mail in mails {
-		'parserloop: for parser in parsers {
-			if !parser.can_parse(&mail) {
-				continue;
-			}
+	// Give every parser the mails that no parser before it could parse
+	for parser in parsers {
+		let (parsable_mails, rest): (Vec<Mail>, Vec<Mail>) = unparsed_mails
+			.into_iter()
+			.partition(|mail| parser.can_parse(mail));
+		unparsed_mails = rest;
+
+		if parsable_mails.is_empty() {
+			continue;
+		}
+
+		let mail_refs = parsable_mails.iter().collect::<Vec<&Mail>>();
+		let results = parser.parse_many(&mail_refs).await;
+
+		for (mail, result) in parsable_mails.into_iter().zip(results) {
+			match result {
+				Ok(mut transactions) => {
+					// keep who sent us the mail and what they said, for forwarded notices
+					if let Some(forwarded_by) = &mail.forwarded_by {
+						for transaction in &mut transactions {
+							transaction
+								.metadata
+								.insert("forwarded_by".to_owned(), forwarded_by.clone());
+						}
+					}
+					if let Some(forward_note) = &mail.forward_note {
+						// on one line, without the separator of the notes column
+						let forward_note = forward_note
+							.split_whitespace()
+							.collect::<Vec<&str>>()
+							.join(" ")
+							.replace(';', ",");
+						for transaction in &mut transactions {
+							transaction
+								.metadata
+								.insert("forward_note".to_owned(), forward_note.clone());
+						}
+					}
 
-			match parser.parse(&mail).await {
-				Ok(transactions) => {
 					#[cfg(debug_assertions)]
 					debug!("Transactions: {:#?}", transactions);
 
@@ -45,9 +97,11 @@ pub async fn parse_emails(
 						transactions.len()
 					);
 					map.insert(mail, transactions);
-					break 'parserloop; // Break after first parse success
 				}
-				Err(e) => error!("Mail: [{}]. Could not parse mail: {}", mail.subject, e),
+				Err(e) => {
+					error!("Mail: [{}]. Could not parse mail: {}", mail.subject, e);
+					unparsed_mails.push(mail);
+				}
 			}
 		}
 	}
@@ -80,3 +134,118 @@ fn parse_regex_first_match(
 	}
 	return Ok(None);
 }
+
+/// Reads `Label: value` (or `■ラベル：値`) lines into a map, keeping the first value of each label.
+fn parse_labeled_lines(text: &str) -> HashMap<String, String> {
+	let mut map = HashMap::new();
+
+	for line in text.lines() {
+		let Some((label, value)) = line.split_once([':', '：']) else {
+			continue;
+		};
+		let label = label.trim().trim_start_matches(['■', '●', '・']).trim();
+		if label.is_empty() || label.contains("http") {
+			continue;
+		}
+
+		map.entry(label.to_owned())
+			.or_insert(value.trim().to_owned());
+	}
+
+	map
+}
+
+/// Parses an Indonesian-formatted amount such as `Rp 1.250.000,00` or `IDR 57.300`, where `.`
+/// separates thousands and `,` starts the decimals. Amounts that don't follow this, such as the
+/// English `IDR 65,000.00`, are rejected rather than guessed at.
+fn parse_idr_amount(text: &str) -> Result<Decimal, ErrorInterface> {
+	let text = text.trim();
+	let text = text
+		.strip_prefix("IDR")
+		.or(text.strip_prefix("Rp."))
+		.or(text.strip_prefix("Rp"))
+		.unwrap_or(text)
+		.trim();
+	let amount_regex = Regex::new(r"^([0-9]{1,3}(\.[0-9]{3})*|[0-9]+)(,[0-9]{1,2})?$")?;
+	if !amount_regex.is_match(text) {
+		return Err(format!("Amount is not in the Indonesian format: {}", text).into());
+	}
+	let number = text.replace(".", "").replace(",", ".");
+
+	Ok(Decimal::from_str_exact(&number)?.normalize())
+}
+
+/// Parses a yen amount such as `4,980 円`, `¥4,980` or `￥4,980`.
+fn parse_jpy_amount(text: &str) -> Option<Decimal> {
+	let text = text.trim();
+	let amount = text
+		.strip_suffix("円")
+		.or(text.strip_prefix("¥"))
+		.or(text.strip_prefix("￥"))?;
+
+	Decimal::from_str_exact(&amount.trim().replace(",", "")).ok()
+}
+
+/// UTC offsets, in hours, of the time zone abbreviations mails write their times with.
+const TIMEZONE_ABBREVIATIONS: &[(&str, i32)] = &[
+	("UTC", 0),
+	("GMT", 0),
+	("JST", 9),
+	("KST", 9),
+	("WIB", 7),
+	("WITA", 8),
+	("WIT", 9),
+	("SGT", 8),
+	("CET", 1),
+	("CEST", 2),
+	("EST", -5),
+	("EDT", -4),
+	("PST", -8),
+	("PDT", -7),
+];
+
+/// Parses a local date and time with the first format that fits. A trailing time zone
+/// abbreviation such as `WIB` or `PST` is used if there is one, otherwise the time is taken to be
+/// in the given time zone. Unknown abbreviations are an error rather than a guess.
+fn parse_local_datetime(
+	text: &str,
+	formats: &[&str],
+	timezone: chrono_tz::Tz,
+) -> Result<DateTime<Utc>, ErrorInterface> {
+	let text = text.trim();
+	let (text, abbreviation) = match text.rsplit_once(char::is_whitespace) {
+		Some((rest, last))
+			if !["AM", "PM"].contains(&last)
+				&& last.len() >= 2
+				&& last.chars().all(|c| c.is_ascii_uppercase()) =>
+		{
+			(rest.trim(), Some(last))
+		}
+		_ => (text, None),
+	};
+	let parsed_datetime = formats
+		.iter()
+		.find_map(|f| NaiveDateTime::parse_from_str(text, f).ok())
+		.ok_or(format!("Unknown datetime format: {}", text))?;
+
+	let Some(abbreviation) = abbreviation else {
+		let local_datetime = timezone
+			.from_local_datetime(&parsed_datetime)
+			.single()
+			.ok_or(format!("Ambiguous datetime: {}", text))?;
+		return Ok(local_datetime.with_timezone(&Utc));
+	};
+
+	let hours = TIMEZONE_ABBREVIATIONS
+		.iter()
+		.find(|(a, _)| *a == abbreviation)
+		.map(|(_, hours)| *hours)
+		.ok_or(format!("Unknown time zone: {}", abbreviation))?;
+	let offset = FixedOffset::east_opt(hours * 3600).ok_or("Invalid time zone offset")?;
+	let local_datetime = offset
+		.from_local_datetime(&parsed_datetime)
+		.single()
+		.ok_or(format!("Ambiguous datetime: {}", text))?;
+
+	Ok(local_datetime.with_timezone(&Utc))
+}
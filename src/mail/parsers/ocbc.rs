@@ -1,8 +1,11 @@
+use std::collections::{BTreeMap, HashMap};
+
 use chrono::{NaiveDateTime, TimeZone, Utc};
-use rust_decimal::{Decimal, prelude::FromPrimitive};
+use regex::Regex;
+use rust_decimal::Decimal;
 
 use crate::ErrorInterface;
-use crate::mail::{Mail, parsers::parse_regex_first_match};
+use crate::mail::Mail;
 
 use super::{EmailParsingScheme, Transaction};
 
@@ -10,48 +13,228 @@ pub struct OcbcPaymentNotificationScheme {
 	pub account: String,
 }
 
+/// The layout of one kind of OCBC notification.
+struct Template {
+	kind: &'static str,
+	subject_prefix: &'static str,
+	counterparty_labels: &'static [&'static str],
+	date_labels: &'static [&'static str],
+	incoming: bool,
+}
+
+const TEMPLATES: &[Template] = &[
+	Template {
+		kind: "payment",
+		subject_prefix: "Successful Payment to ",
+		counterparty_labels: &["PAYMENT TO"],
+		date_labels: &["PAYMENT DATE"],
+		incoming: false,
+	},
+	Template {
+		kind: "qris",
+		subject_prefix: "Successful QRIS Payment to ",
+		counterparty_labels: &["MERCHANT NAME", "PAYMENT TO"],
+		date_labels: &["TRANSACTION DATE", "PAYMENT DATE"],
+		incoming: false,
+	},
+	Template {
+		kind: "transfer_out",
+		subject_prefix: "Successful Transfer to ",
+		counterparty_labels: &["BENEFICIARY NAME", "TRANSFER TO"],
+		date_labels: &["TRANSFER DATE", "TRANSACTION DATE"],
+		incoming: false,
+	},
+	Template {
+		kind: "transfer_in",
+		subject_prefix: "Incoming Transfer from ",
+		counterparty_labels: &["SENDER NAME", "TRANSFER FROM"],
+		date_labels: &["TRANSACTION DATE", "TRANSFER DATE"],
+		incoming: true,
+	},
+	Template {
+		kind: "top_up",
+		subject_prefix: "Successful Top Up to ",
+		counterparty_labels: &["BILLER NAME", "TOP UP TO"],
+		date_labels: &["TRANSACTION DATE", "PAYMENT DATE"],
+		incoming: false,
+	},
+	Template {
+		kind: "card",
+		subject_prefix: "Card Transaction at ",
+		counterparty_labels: &["MERCHANT NAME", "MERCHANT"],
+		date_labels: &["TRANSACTION DATE"],
+		incoming: false,
+	},
+];
+
+impl OcbcPaymentNotificationScheme {
+	/// Finds the template by its subject anywhere in the mail's, which may have a prefix such as
+	/// `Fwd: `.
+	fn find_template(&self, mail: &Mail) -> Option<&'static Template> {
+		TEMPLATES
+			.iter()
+			.find(|t| mail.subject.contains(t.subject_prefix))
+	}
+}
+
 #[async_trait::async_trait]
 impl EmailParsingScheme for OcbcPaymentNotificationScheme {
 	fn can_parse(&self, mail: &Mail) -> bool {
-		mail.from.eq("Notifikasi OCBC <notifikasi@ocbc.id>")
-			&& mail.subject.contains("Successful Payment to")
+		mail.from.contains("notifikasi@ocbc.id") && self.find_template(mail).is_some()
 	}
 
 	async fn parse(&self, mail: &Mail) -> Result<Vec<Transaction>, ErrorInterface> {
+		let template = self
+			.find_template(mail)
+			.ok_or("Unknown OCBC notification")?;
+		let fields = read_fields(&mail.body)?;
+		let find_field = |labels: &[&str]| labels.iter().find_map(|l| fields.get(*l));
+
 		// Amount
-		let amount_captures = parse_regex_first_match(&mail.body, r"IDR\s+([0-9\,]+)", 1)?;
-		let amount_captures = amount_captures.ok_or("No amount data found")?;
-		let amount_string = amount_captures
-			.first()
-			.ok_or("No amount data found")?
-			.to_owned();
-		let amount_string = amount_string.replace(",", "");
-		let amount = amount_string.parse::<u32>()?;
-		let mut amount = Decimal::from_u32(amount).ok_or("Failed to parse amount")?;
-		amount.set_sign_negative(true);
+		let amount_string = fields.get("AMOUNT").ok_or("No amount data found")?;
+		let amount_string = amount_string
+			.trim()
+			.trim_start_matches("IDR")
+			.trim()
+			.replace(",", "");
+		let mut amount = Decimal::from_str_exact(&amount_string)?.normalize();
+		amount.set_sign_negative(!template.incoming);
 
 		// Datetime
-		let datetime_captures = parse_regex_first_match(
-			&mail.body,
-			r#"<b>PAYMENT DATE:<\/b><br\/>\s*<span style="color:#5f5f5f">(.+)\sWIB</span></span>"#,
-			1,
-		)?;
-		let datetime_captures = datetime_captures.ok_or("No datetime data found")?;
-		let datetime_string = datetime_captures.first().ok_or("No datetime data found")?;
-		let parsed_datetime = NaiveDateTime::parse_from_str(&datetime_string, "%d %b %Y %H:%M:%S")?;
+		let datetime_string = find_field(template.date_labels).ok_or("No datetime data found")?;
+		let datetime_string = datetime_string.trim().trim_end_matches("WIB").trim();
+		let parsed_datetime =
+			NaiveDateTime::parse_from_str(datetime_string, "%d %b %Y %H:%M:%S")
+				.or_else(|_| NaiveDateTime::parse_from_str(datetime_string, "%d %b %Y %H:%M"))?;
 		let wib_datetime = chrono_tz::Asia::Jakarta
 			.from_local_datetime(&parsed_datetime)
 			.unwrap();
 		let datetime = wib_datetime.with_timezone(&Utc);
 
 		// Subject
-		let subject = mail.subject.trim().replace("Successful Payment to ", "");
+		let counterparty = match find_field(template.counterparty_labels) {
+			Some(counterparty) => counterparty.trim().to_owned(),
+			None => mail
+				.subject
+				.split_once(template.subject_prefix)
+				.map(|(_, counterparty)| counterparty.trim().to_owned())
+				.unwrap_or_default(),
+		};
+
+		let mut metadata = BTreeMap::new();
+		metadata.insert("kind".to_owned(), template.kind.to_owned());
+		metadata.insert("counterparty".to_owned(), counterparty.clone());
+		if let Some(reference) = find_field(&["REFERENCE NUMBER", "REFERENCE NO"]) {
+			metadata.insert("reference".to_owned(), reference.trim().to_owned());
+		}
 
 		Ok(vec![Transaction {
-			subject: Some(subject),
+			subject: Some(counterparty),
 			datetime,
 			amount,
 			account: self.account.clone(),
+			category: None,
+			receipt: None,
+			metadata,
 		}])
 	}
 }
+
+/// Reads the `LABEL:` and value pairs the notifications are made of.
+fn read_fields(body: &str) -> Result<HashMap<String, String>, ErrorInterface> {
+	let field_regex = Regex::new(r#"<b>([A-Z ]+):</b><br/>\s*<span[^>]*>([^<]+)</span>"#)?;
+
+	Ok(field_regex
+		.captures_iter(body)
+		.map(|c| (c[1].trim().to_owned(), c[2].trim().to_owned()))
+		.collect())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::mail::Mail;
+	use crate::mail::parsers::EmailParsingScheme;
+
+	use super::OcbcPaymentNotificationScheme;
+
+	#[tokio::test]
+	async fn parses_every_notification_template() {
+		let scheme = OcbcPaymentNotificationScheme {
+			account: "OCBC".into(),
+		};
+		let cases = [
+			(
+				"Successful Payment to TOKOPEDIA",
+				include_str!("fixtures/ocbc_payment.html"),
+				"payment TOKOPEDIA -150000 2025-01-05 05:34:56 UTC",
+			),
+			(
+				"Successful QRIS Payment to KOPI KENANGAN",
+				include_str!("fixtures/ocbc_qris.html"),
+				"qris KOPI KENANGAN GRAND INDONESIA -38500 2025-01-06 01:15:20 UTC",
+			),
+			(
+				"Successful Transfer to BUDI SANTOSO",
+				include_str!("fixtures/ocbc_transfer_out.html"),
+				"transfer_out BUDI SANTOSO -2500000 2025-01-07 07:02:11 UTC",
+			),
+			(
+				"Incoming Transfer from PT MAJU JAYA",
+				include_str!("fixtures/ocbc_transfer_in.html"),
+				"transfer_in PT MAJU JAYA 12750000.5 2025-01-08 02:00:00 UTC",
+			),
+			(
+				"Successful Top Up to GOPAY",
+				include_str!("fixtures/ocbc_top_up.html"),
+				"top_up GOPAY -200000 2025-01-09 13:45:00 UTC",
+			),
+			(
+				"Card Transaction at GRAB* A-7XYZ",
+				include_str!("fixtures/ocbc_card.html"),
+				"card GRAB* A-7XYZ -64000 2025-01-10 11:30:05 UTC",
+			),
+			(
+				"Fwd: [OCBC] Card Transaction at GRAB* A-7XYZ",
+				include_str!("fixtures/ocbc_card.html"),
+				"card GRAB* A-7XYZ -64000 2025-01-10 11:30:05 UTC",
+			),
+		];
+
+		for (subject, body, expected) in cases {
+			let mut mail = Mail::create_test_mail();
+			mail.from = "Notifikasi OCBC <notifikasi@ocbc.id>".into();
+			mail.subject = subject.into();
+			mail.body = body.into();
+			assert!(scheme.can_parse(&mail), "cannot parse [{}]", subject);
+
+			let transactions = scheme.parse(&mail).await.unwrap();
+			assert_eq!(1, transactions.len());
+			let transaction = &transactions[0];
+			assert_eq!(
+				expected,
+				format!(
+					"{} {} {} {}",
+					transaction.metadata["kind"],
+					transaction.subject.as_deref().unwrap(),
+					transaction.amount,
+					transaction.datetime
+				)
+			);
+			assert_eq!(
+				transaction.subject.as_ref(),
+				transaction.metadata.get("counterparty")
+			);
+		}
+	}
+
+	#[test]
+	fn ignores_other_ocbc_mails() {
+		let scheme = OcbcPaymentNotificationScheme {
+			account: "OCBC".into(),
+		};
+		let mut mail = Mail::create_test_mail();
+		mail.from = "Notifikasi OCBC <notifikasi@ocbc.id>".into();
+		mail.subject = "Promo Spesial Akhir Tahun".into();
+		assert!(!scheme.can_parse(&mail));
+	}
+}
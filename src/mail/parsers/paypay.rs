@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+
+use crate::ErrorInterface;
+use crate::mail::{
+	Mail,
+	parsers::{parse_jpy_amount, parse_labeled_lines, parse_local_datetime},
+};
+
+use super::{EmailParsingScheme, Transaction};
+
+const DATETIME_FORMATS: &[&str] = &[
+	"%Y/%m/%d %H:%M:%S",
+	"%Y/%m/%d %H:%M",
+	"%Y年%m月%d日 %H時%M分",
+	"%Y年%m月%d日 %H:%M",
+];
+
+pub struct PayPayParsingScheme {
+	pub account: String,
+}
+
+impl PayPayParsingScheme {
+	fn is_refund(&self, mail: &Mail) -> bool {
+		mail.subject.contains("返金")
+			|| mail.subject.contains("取消")
+			|| mail.subject.contains("キャンセル")
+			|| mail.body.contains("返金金額")
+	}
+}
+
+#[async_trait::async_trait]
+impl EmailParsingScheme for PayPayParsingScheme {
+	fn can_parse(&self, mail: &Mail) -> bool {
+		mail.from.contains("paypay.ne.jp")
+			&& ["支払い完了", "お支払いが完了", "返金", "取消", "キャンセル"]
+				.iter()
+				.any(|s| mail.subject.contains(s))
+	}
+
+	async fn parse(&self, mail: &Mail) -> Result<Vec<Transaction>, ErrorInterface> {
+		let fields = parse_labeled_lines(&mail.body);
+		let find_field = |labels: &[&str]| labels.iter().find_map(|l| fields.get(*l));
+		let is_refund = self.is_refund(mail);
+
+		// Amount
+		let amount_string = find_field(&["支払い金額", "お支払い金額", "返金金額", "取消金額"])
+			.ok_or("No amount data found")?;
+		let mut amount = parse_jpy_amount(amount_string).ok_or("Failed to parse amount")?;
+		amount.set_sign_negative(!is_refund);
+
+		// Datetime
+		let datetime_string = find_field(&["支払い日時", "お支払い日時", "返金日時", "取消日時"])
+			.ok_or("No datetime data found")?;
+		let datetime =
+			parse_local_datetime(datetime_string, DATETIME_FORMATS, chrono_tz::Asia::Tokyo)?;
+
+		// Subject
+		let subject = find_field(&["支払い先", "お支払い先", "返金元", "加盟店"])
+			.ok_or("No subject data found")?
+			.clone();
+
+		let mut metadata = BTreeMap::new();
+		if is_refund {
+			metadata.insert("kind".to_owned(), "refund".to_owned());
+		}
+		if let Some(payment_method) = find_field(&["支払い方法", "お支払い方法"]) {
+			metadata.insert("payment_method".to_owned(), payment_method.clone());
+		}
+		if let Some(transaction_id) = find_field(&["取引番号", "決済番号"]) {
+			metadata.insert("transaction_id".to_owned(), transaction_id.clone());
+		}
+
+		Ok(vec![Transaction {
+			subject: Some(subject),
+			datetime,
+			amount,
+			account: self.account.clone(),
+			category: None,
+			receipt: None,
+			metadata,
+		}])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::mail::Mail;
+	use crate::mail::parsers::EmailParsingScheme;
+
+	use super::PayPayParsingScheme;
+
+	#[tokio::test]
+	async fn parses_payments_and_refunds() {
+		let scheme = PayPayParsingScheme {
+			account: "PayPay".into(),
+		};
+
+		let mut mail = Mail::create_test_mail();
+		mail.from = "PayPay <no-reply@paypay.ne.jp>".into();
+		mail.subject = "【PayPay】支払い完了のお知らせ".into();
+		mail.body = include_str!("fixtures/paypay_payment.txt").into();
+		assert!(scheme.can_parse(&mail));
+
+		let transactions = scheme.parse(&mail).await.unwrap();
+		assert_eq!(
+			Some("セブン-イレブン 神戸三宮店".to_owned()),
+			transactions[0].subject
+		);
+		assert_eq!("-580", transactions[0].amount.to_string());
+		assert_eq!(
+			"2025-01-16 10:02:00 UTC",
+			transactions[0].datetime.to_string()
+		);
+		assert_eq!("PayPay残高", transactions[0].metadata["payment_method"]);
+
+		mail.subject = "【PayPay】返金完了のお知らせ".into();
+		mail.body = include_str!("fixtures/paypay_refund.txt").into();
+		assert!(scheme.can_parse(&mail));
+
+		let transactions = scheme.parse(&mail).await.unwrap();
+		assert_eq!("1200", transactions[0].amount.to_string());
+		assert_eq!("refund", transactions[0].metadata["kind"]);
+		assert_eq!(
+			"2025-01-17 05:30:00 UTC",
+			transactions[0].datetime.to_string()
+		);
+	}
+}
@@ -1,11 +1,19 @@
+use std::collections::{BTreeMap, HashMap};
+
 use chrono::{NaiveDateTime, TimeZone, Utc};
 use regex::Regex;
-use rust_decimal::{Decimal, prelude::FromPrimitive};
 
-use crate::{ErrorInterface, mail::Mail};
+use crate::ErrorInterface;
+use crate::mail::{Mail, parsers::parse_jpy_amount};
 
 use super::{EmailParsingScheme, Transaction};
 
+/// Fields that may hold the amount in the original currency of a foreign purchase.
+const FOREIGN_AMOUNT_FIELDS: &[&str] = &["現地通貨額", "現地利用額", "外貨利用額", "利用外貨額"];
+/// Fields that may hold the yen amount when `利用金額` is in a foreign currency.
+const YEN_AMOUNT_FIELDS: &[&str] = &["円換算額", "円換算金額", "支払金額"];
+const EXCHANGE_RATE_FIELDS: &[&str] = &["換算レート", "為替レート", "適用レート"];
+
 pub struct RakutenCardParsingScheme {
 	pub account: String,
 }
@@ -26,60 +34,196 @@ impl EmailParsingScheme for RakutenCardParsingScheme {
 	async fn parse(&self, mail: &Mail) -> Result<Vec<Transaction>, ErrorInterface> {
 		let mut transactions = vec![];
 
-		let regex = Regex::new(
-			"■利用日: ([0-9/]+)\n■利用先: (.+)\n■利用者: 本人\n■支払方法: [0-9]*回\n■利用金額: ([0-9,]+) 円\n■支払月: [0-9/]+",
-		)?;
-		for captures in regex.captures_iter(&mail.body) {
-			let transaction: Result<Option<Transaction>, ErrorInterface> = 'parseOne: {
-				// Subject
-				let subject = captures
-					.get(2)
-					.ok_or("No subject data found")?
-					.as_str()
-					.to_owned();
-				if self.in_skip_list(&subject) {
-					break 'parseOne Ok(None);
-				}
-
-				// Datetime
-				let datetime_string = captures
-					.get(1)
-					.ok_or("No datetime data found")?
-					.as_str()
-					.to_owned();
-				let datetime_string = datetime_string + " 00:00:00";
-				let parsed_datetime =
-					NaiveDateTime::parse_from_str(&datetime_string, "%Y/%m/%d %H:%M:%S")?;
-				let jst_datetime = chrono_tz::Asia::Tokyo
-					.from_local_datetime(&parsed_datetime)
-					.unwrap();
-				let datetime = jst_datetime.with_timezone(&Utc);
-
-				// Amount
-				let amount_string: String = captures
-					.get(3)
-					.ok_or("No amount data found")?
-					.as_str()
-					.to_owned();
-				let amount_string = amount_string.replace(",", "");
-				let amount = amount_string.parse::<u32>()?;
-				let mut amount = Decimal::from_u32(amount).ok_or("Failed to parse amount")?;
-				amount.set_sign_negative(true);
-
-				Ok(Some(Transaction {
-					subject: Some(subject),
-					datetime,
-					amount,
-					account: self.account.clone(),
-				}))
+		for fields in read_entries(&mail.body)? {
+			// skip blocks that are not purchases, e.g. the card name and number
+			let (Some(date), Some(subject), Some(amount)) = (
+				fields.get("利用日"),
+				fields.get("利用先"),
+				fields.get("利用金額"),
+			) else {
+				continue;
 			};
+			if self.in_skip_list(subject) {
+				continue;
+			}
 
-			match transaction? {
-				Some(t) => transactions.push(t),
-				None => continue,
+			// Datetime
+			let datetime_string = format!("{} 00:00:00", date);
+			let parsed_datetime =
+				NaiveDateTime::parse_from_str(&datetime_string, "%Y/%m/%d %H:%M:%S")?;
+			let jst_datetime = chrono_tz::Asia::Tokyo
+				.from_local_datetime(&parsed_datetime)
+				.unwrap();
+			let datetime = jst_datetime.with_timezone(&Utc);
+
+			// Amount, in yen even for foreign currency purchases
+			let foreign_amount = parse_foreign_amount(amount).or_else(|| {
+				FOREIGN_AMOUNT_FIELDS
+					.iter()
+					.find_map(|f| fields.get(*f).and_then(|v| parse_foreign_amount(v)))
+			});
+			let mut amount = parse_jpy_amount(amount)
+				.or_else(|| {
+					YEN_AMOUNT_FIELDS
+						.iter()
+						.find_map(|f| fields.get(*f).and_then(|v| parse_jpy_amount(v)))
+				})
+				.ok_or(format!("No yen amount found for [{}]", subject))?;
+			amount.set_sign_negative(true);
+
+			let mut metadata = BTreeMap::new();
+			if let Some((original_amount, original_currency)) = foreign_amount {
+				metadata.insert("original_amount".to_owned(), original_amount);
+				metadata.insert("original_currency".to_owned(), original_currency);
+			}
+			if let Some(rate) = EXCHANGE_RATE_FIELDS.iter().find_map(|f| fields.get(*f)) {
+				metadata.insert("exchange_rate".to_owned(), rate.clone());
+			}
+			for (field, key) in [
+				("利用者", "card_user"),
+				("支払方法", "payment_method"),
+				("支払月", "payment_month"),
+			] {
+				if let Some(value) = fields.get(field) {
+					metadata.insert(key.to_owned(), value.clone());
+				}
 			}
+
+			transactions.push(Transaction {
+				subject: Some(subject.clone()),
+				datetime,
+				amount,
+				account: self.account.clone(),
+				category: None,
+				receipt: None,
+				metadata,
+			});
 		}
 
 		Ok(transactions)
 	}
 }
+
+/// Parses a foreign currency amount such as `20.00 USD` or `USD 20.00` into the amount and the
+/// currency code.
+fn parse_foreign_amount(text: &str) -> Option<(String, String)> {
+	let regex = Regex::new(
+		r"^(?:([A-Z]{3})\s*([0-9][0-9,]*(?:\.[0-9]+)?)|([0-9][0-9,]*(?:\.[0-9]+)?)\s*([A-Z]{3}))$",
+	)
+	.unwrap();
+	let captures = regex.captures(text.trim())?;
+	let amount = captures
+		.get(2)
+		.or(captures.get(3))?
+		.as_str()
+		.replace(",", "");
+	let currency = captures.get(1).or(captures.get(4))?.as_str().to_owned();
+
+	match currency.as_str() {
+		"JPY" => None,
+		_ => Some((amount, currency)),
+	}
+}
+
+/// Splits the mail into runs of `■field: value` lines. A run ends at any other line, or when a
+/// field shows up again, so the fields of one purchase can come in any order.
+fn read_entries(body: &str) -> Result<Vec<HashMap<String, String>>, ErrorInterface> {
+	let field_regex = Regex::new(r"^■\s*([^:：]+?)\s*[:：]\s*(.*?)\s*$")?;
+	let mut entries = vec![];
+	let mut current: HashMap<String, String> = HashMap::new();
+
+	for line in body.replace("\r\n", "\n").replace('\r', "\n").lines() {
+		let Some(captures) = field_regex.captures(line.trim()) else {
+			if !current.is_empty() {
+				entries.push(std::mem::take(&mut current));
+			}
+			continue;
+		};
+
+		let field = captures[1].to_owned();
+		if current.contains_key(&field) {
+			entries.push(std::mem::take(&mut current));
+		}
+		current.insert(field, captures[2].to_owned());
+	}
+	if !current.is_empty() {
+		entries.push(current);
+	}
+
+	Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::mail::Mail;
+	use crate::mail::parsers::EmailParsingScheme;
+
+	use super::RakutenCardParsingScheme;
+
+	#[tokio::test]
+	async fn parses_family_revolving_and_bonus_payments() {
+		let mut mail = Mail::create_test_mail();
+		// mails sometimes come with CRLF line endings
+		mail.body = include_str!("fixtures/rakuten_card_variants.txt").replace("\n", "\r\n");
+		let scheme = RakutenCardParsingScheme {
+			account: "Rakuten".into(),
+		};
+
+		let transactions = scheme.parse(&mail).await.unwrap();
+		assert_eq!(4, transactions.len());
+
+		let summary = transactions
+			.iter()
+			.map(|t| {
+				format!(
+					"{} {} {} {}",
+					t.subject.as_deref().unwrap(),
+					t.amount,
+					t.metadata["card_user"],
+					t.metadata["payment_method"],
+				)
+			})
+			.collect::<Vec<String>>();
+		assert_eq!(
+			vec![
+				"ローソン 三宮店 -350 本人 1回",
+				"ヨドバシカメラ マルチメディア梅田 -128000 家族 リボ",
+				"ANA 航空券 -45600 本人 ボーナス一括",
+				"ニトリ 神戸店 -60000 家族 分割(3回)",
+			],
+			summary
+		);
+		assert_eq!("2025/02", transactions[0].metadata["payment_month"]);
+		assert_eq!("2025/07", transactions[2].metadata["payment_month"]);
+		assert_eq!(
+			"2025-01-07 15:00:00 UTC",
+			transactions[1].datetime.to_string()
+		);
+	}
+
+	#[tokio::test]
+	async fn parses_foreign_currency_purchases() {
+		let mut mail = Mail::create_test_mail();
+		mail.body = include_str!("fixtures/rakuten_card_foreign.txt").into();
+		let scheme = RakutenCardParsingScheme {
+			account: "Rakuten".into(),
+		};
+
+		let transactions = scheme.parse(&mail).await.unwrap();
+		assert_eq!(3, transactions.len());
+
+		// the yen amount is the one written to the sheet
+		assert_eq!("-3210", transactions[0].amount.to_string());
+		assert_eq!("20.00", transactions[0].metadata["original_amount"]);
+		assert_eq!("USD", transactions[0].metadata["original_currency"]);
+		assert_eq!("160.500", transactions[0].metadata["exchange_rate"]);
+
+		assert_eq!("-1845", transactions[1].amount.to_string());
+		assert_eq!("10.99", transactions[1].metadata["original_amount"]);
+		assert_eq!("EUR", transactions[1].metadata["original_currency"]);
+		assert_eq!("167.900", transactions[1].metadata["exchange_rate"]);
+
+		assert_eq!("-580", transactions[2].amount.to_string());
+		assert!(!transactions[2].metadata.contains_key("original_amount"));
+	}
+}
@@ -1,5 +1,8 @@
+use std::collections::BTreeMap;
+
 use chrono::{NaiveDateTime, TimeZone, Utc};
-use rust_decimal::{Decimal, prelude::FromPrimitive};
+use regex::Regex;
+use rust_decimal::Decimal;
 
 use crate::ErrorInterface;
 use crate::mail::{Mail, parsers::parse_regex_first_match};
@@ -7,32 +10,104 @@ use crate::mail::{Mail, parsers::parse_regex_first_match};
 use super::{EmailParsingScheme, Transaction};
 
 pub struct RakutenPayParsingScheme {
+	/// Account for the part paid by card, and for everything else not configured below.
 	pub account: String,
+	/// Account for the part paid with 楽天キャッシュ.
+	pub cash_account: Option<String>,
+	/// Account for the part paid with 楽天ポイント. Points are only kept as metadata if not set.
+	pub points_account: Option<String>,
+}
+
+/// One line of the お支払い内訳 (or 返金内訳) section.
+#[derive(Debug)]
+struct PaymentPart {
+	name: String,
+	amount: Decimal,
+	is_points: bool,
+}
+
+impl RakutenPayParsingScheme {
+	fn is_cancellation(&self, mail: &Mail) -> bool {
+		mail.subject.contains("取消") || mail.body.contains("取消金額")
+	}
+
+	/// Splits the total into one amount per account, following where the money came from. A
+	/// payment made with points only is kept as a zero amount on the main account if there is no
+	/// account for points, so the points used are not lost. Points given back by a cancellation
+	/// are noted down as refunded instead of used.
+	fn split_by_account(
+		&self,
+		total: Decimal,
+		parts: &[PaymentPart],
+		is_cancellation: bool,
+		metadata: &mut BTreeMap<String, String>,
+	) -> Vec<(String, Decimal)> {
+		let points_key = match is_cancellation {
+			true => "points_refunded",
+			false => "points_used",
+		};
+		let mut amounts: Vec<(String, Decimal)> = vec![];
+		let mut add = |account: &String, amount: Decimal| {
+			match amounts.iter_mut().find(|(a, _)| a == account) {
+				Some((_, sum)) => *sum += amount,
+				None => amounts.push((account.clone(), amount)),
+			};
+		};
+
+		for part in parts.iter().filter(|p| !p.amount.is_zero()) {
+			if part.is_points {
+				metadata.insert(points_key.to_owned(), part.amount.to_string());
+				if let Some(points_account) = &self.points_account {
+					add(points_account, part.amount);
+				}
+			} else if part.name.contains("クーポン") {
+				metadata.insert("coupon_discount".to_owned(), part.amount.abs().to_string());
+			} else if part.name.contains("キャッシュ") {
+				add(
+					self.cash_account.as_ref().unwrap_or(&self.account),
+					part.amount,
+				);
+			} else {
+				metadata.insert("charged_to".to_owned(), part.name.clone());
+				add(&self.account, part.amount);
+			}
+		}
+
+		// mails without a breakdown are charged to the main account in full
+		if amounts.is_empty() {
+			let amount = match metadata.contains_key(points_key) {
+				true => Decimal::ZERO,
+				false => total,
+			};
+			amounts.push((self.account.clone(), amount));
+		}
+
+		amounts
+	}
 }
 
 #[async_trait::async_trait]
 impl EmailParsingScheme for RakutenPayParsingScheme {
 	fn can_parse(&self, mail: &Mail) -> bool {
 		mail.subject.contains("楽天ペイアプリご利用内容確認メール")
+			|| mail.subject.contains("楽天ペイアプリご利用取消")
 	}
 
 	async fn parse(&self, mail: &Mail) -> Result<Vec<Transaction>, ErrorInterface> {
 		// Amount
-		let amount_captures = parse_regex_first_match(&mail.body, r"決済総額\s+([0-9\,]+)", 1)?;
+		let amount_captures =
+			parse_regex_first_match(&mail.body, r"(?:決済総額|取消金額|返金額)\s+([0-9\,]+)", 1)?;
 		let amount_captures = amount_captures.ok_or("No amount data found")?;
 		let amount_string = amount_captures
 			.first()
 			.ok_or("No amount data found")?
 			.to_owned();
-		let amount_string = amount_string.replace(",", "");
-		let amount = amount_string.parse::<u32>()?;
-		let mut amount = Decimal::from_u32(amount).ok_or("Failed to parse amount")?;
-		amount.set_sign_negative(true);
+		let total = Decimal::from_str_exact(&amount_string.replace(",", ""))?;
 
 		// Datetime
 		let datetime_captures = parse_regex_first_match(
 			&mail.body,
-			r"ご利用日時\s+([0-9]+)\/([0-9]+)\/([0-9]+)\(.\) ([0-9]+):([0-9]+)",
+			r"(?:ご利用日時|取消日時)\s+([0-9]+)\/([0-9]+)\/([0-9]+)\(.\) ([0-9]+):([0-9]+)",
 			5,
 		)?;
 		let datetime_captures = datetime_captures.ok_or("No datetime data found")?;
@@ -53,13 +128,163 @@ impl EmailParsingScheme for RakutenPayParsingScheme {
 		// Subject
 		let subject_captures = parse_regex_first_match(&mail.body, r"ご利用店舗\s+(.+)", 1)?;
 		let subject_captures = subject_captures.ok_or("No subject data found")?;
-		let subject = subject_captures.first().unwrap().to_owned();
+		let subject = subject_captures.first().unwrap().trim().to_owned();
+
+		let mut metadata = BTreeMap::new();
+		metadata.insert("total".to_owned(), total.to_string());
+		let is_cancellation = self.is_cancellation(mail);
+		if is_cancellation {
+			metadata.insert("kind".to_owned(), "refund".to_owned());
+		}
+
+		let parts = read_payment_parts(&mail.body)?;
+		let amounts = self.split_by_account(total, &parts, is_cancellation, &mut metadata);
+
+		Ok(amounts
+			.into_iter()
+			.map(|(account, mut amount)| {
+				// purchases take money out, cancellations put it back
+				amount.set_sign_negative(!is_cancellation && !amount.is_zero());
+				Transaction {
+					subject: Some(subject.clone()),
+					datetime,
+					amount,
+					account,
+					category: None,
+					receipt: None,
+					metadata: metadata.clone(),
+				}
+			})
+			.collect())
+	}
+}
+
+/// Reads the lines under ■お支払い内訳 (or ■返金内訳) up to the next section.
+fn read_payment_parts(body: &str) -> Result<Vec<PaymentPart>, ErrorInterface> {
+	let part_regex = Regex::new(r"^(.+?)\s+-?([0-9,]+)\s*(円|ポイント|pt)$")?;
+	let mut parts = vec![];
 
-		Ok(vec![Transaction {
-			subject: Some(subject),
-			datetime,
+	let mut in_section = false;
+	for line in body.lines().map(|l| l.trim()) {
+		if line.starts_with("■") {
+			in_section = line.contains("お支払い内訳") || line.contains("返金内訳");
+			continue;
+		}
+		if !in_section {
+			continue;
+		}
+		if line.is_empty() {
+			break;
+		}
+
+		let Some(captures) = part_regex.captures(line) else {
+			continue;
+		};
+		let name = captures[1].trim().to_owned();
+		let amount = Decimal::from_str_exact(&captures[2].replace(",", ""))?;
+		let is_points = &captures[3] != "円" || name.contains("ポイント");
+		parts.push(PaymentPart {
+			name,
 			amount,
-			account: self.account.clone(),
-		}])
+			is_points,
+		});
+	}
+
+	Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::mail::Mail;
+	use crate::mail::parsers::EmailParsingScheme;
+
+	use super::RakutenPayParsingScheme;
+
+	fn make_mail(subject: &str, body: &str) -> Mail {
+		let mut mail = Mail::create_test_mail();
+		mail.subject = subject.into();
+		mail.body = body.into();
+		mail
+	}
+
+	#[tokio::test]
+	async fn splits_payment_by_source() {
+		let mail = make_mail(
+			"楽天ペイアプリご利用内容確認メール",
+			include_str!("fixtures/rakuten_pay_split.txt"),
+		);
+
+		// points are only noted down if there is no account for them
+		let scheme = RakutenPayParsingScheme {
+			account: "Rakuten".into(),
+			cash_account: Some("Rakuten Cash".into()),
+			points_account: None,
+		};
+		let transactions = scheme.parse(&mail).await.unwrap();
+		assert_eq!(2, transactions.len());
+		assert_eq!("Rakuten Cash", transactions[0].account);
+		assert_eq!("-300", transactions[0].amount.to_string());
+		assert_eq!("Rakuten", transactions[1].account);
+		assert_eq!("-700", transactions[1].amount.to_string());
+		assert_eq!("200", transactions[0].metadata["points_used"]);
+		assert_eq!("100", transactions[0].metadata["coupon_discount"]);
+		assert_eq!("1200", transactions[0].metadata["total"]);
+
+		let scheme = RakutenPayParsingScheme {
+			account: "Rakuten".into(),
+			cash_account: None,
+			points_account: Some("Rakuten Points".into()),
+		};
+		let transactions = scheme.parse(&mail).await.unwrap();
+		let amounts = transactions
+			.iter()
+			.map(|t| format!("{} {}", t.account, t.amount))
+			.collect::<Vec<String>>();
+		assert_eq!(vec!["Rakuten Points -200", "Rakuten -1000"], amounts);
+	}
+
+	#[tokio::test]
+	async fn payment_with_points_only_keeps_the_points_used() {
+		let body = include_str!("fixtures/rakuten_pay_split.txt").replace(
+			"クーポン値引き　　-100円\n楽天ポイント　　200ポイント\n楽天キャッシュ　　300円\n楽天カード　　700円",
+			"楽天ポイント　　1,200ポイント",
+		);
+		let mail = make_mail("楽天ペイアプリご利用内容確認メール", &body);
+
+		let scheme = RakutenPayParsingScheme {
+			account: "Rakuten".into(),
+			cash_account: None,
+			points_account: None,
+		};
+		let transactions = scheme.parse(&mail).await.unwrap();
+		assert_eq!(1, transactions.len());
+		assert_eq!("Rakuten", transactions[0].account);
+		assert!(transactions[0].amount.is_zero());
+		assert_eq!("1200", transactions[0].metadata["points_used"]);
+	}
+
+	#[tokio::test]
+	async fn cancellations_are_refunds() {
+		let mail = make_mail(
+			"楽天ペイアプリご利用取消のお知らせ",
+			include_str!("fixtures/rakuten_pay_cancel.txt"),
+		);
+		let scheme = RakutenPayParsingScheme {
+			account: "Rakuten".into(),
+			cash_account: None,
+			points_account: None,
+		};
+		assert!(scheme.can_parse(&mail));
+
+		let transactions = scheme.parse(&mail).await.unwrap();
+		assert_eq!(1, transactions.len());
+		assert_eq!("1034", transactions[0].amount.to_string());
+		assert_eq!("refund", transactions[0].metadata["kind"]);
+		assert_eq!("200", transactions[0].metadata["points_refunded"]);
+		assert!(!transactions[0].metadata.contains_key("points_used"));
+		assert_eq!(
+			"2025-01-11 01:05:00 UTC",
+			transactions[0].datetime.to_string()
+		);
 	}
 }
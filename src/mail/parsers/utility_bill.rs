@@ -0,0 +1,374 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use regex::Regex;
+
+use crate::ErrorInterface;
+use crate::mail::{
+	Mail,
+	parsers::{parse_jpy_amount, parse_labeled_lines},
+};
+use crate::receipt::{Receipt, ReceiptStore};
+
+use super::{EmailParsingScheme, Transaction};
+
+/// How one company's billing notices look.
+struct Biller {
+	name: &'static str,
+	sender: &'static str,
+	subject_keywords: &'static [&'static str],
+	/// Parts of the subject the card or bank transaction for the bill is expected to have, matched
+	/// regardless of the width of kana and letters.
+	payment_subjects: &'static [&'static str],
+	period_labels: &'static [&'static str],
+	amount_labels: &'static [&'static str],
+	due_date_labels: &'static [&'static str],
+	usage_labels: &'static [&'static str],
+}
+
+const BILLERS: &[Biller] = &[
+	Biller {
+		name: "関西電力",
+		sender: "kepco.co.jp",
+		subject_keywords: &["電気料金", "ご請求金額"],
+		payment_subjects: &["関西電力", "カンサイデンリヨク", "KANSAI ELECTRIC"],
+		period_labels: &["ご使用期間", "使用期間"],
+		amount_labels: &["ご請求金額", "請求金額", "電気料金"],
+		due_date_labels: &[
+			"お支払期日",
+			"お支払い期日",
+			"お支払予定日",
+			"口座振替予定日",
+		],
+		usage_labels: &["ご使用量", "使用電力量"],
+	},
+	Biller {
+		name: "楽天モバイル",
+		sender: "mobile.rakuten.co.jp",
+		subject_keywords: &["ご請求金額", "ご利用料金"],
+		payment_subjects: &["楽天モバイル", "ラクテンモバイル", "RAKUTEN MOBILE"],
+		period_labels: &["ご利用月", "ご利用期間", "対象月"],
+		amount_labels: &["ご請求金額", "請求金額"],
+		due_date_labels: &["お支払い予定日", "お支払予定日", "お支払期日"],
+		usage_labels: &["データ利用量", "データ通信量"],
+	},
+	Biller {
+		name: "神戸市水道局",
+		sender: "city.kobe.lg.jp",
+		subject_keywords: &["水道料金"],
+		payment_subjects: &["神戸市水道", "コウベシスイドウ"],
+		period_labels: &["使用期間", "ご使用期間"],
+		amount_labels: &["請求金額", "ご請求金額", "水道料金・下水道使用料"],
+		due_date_labels: &["支払期限", "お支払期限", "口座振替日"],
+		usage_labels: &["使用水量", "ご使用水量"],
+	},
+];
+
+const DATE_FORMATS: &[&str] = &["%Y年%m月%d日", "%Y/%m/%d", "%Y-%m-%d"];
+
+/// Reads billing notices from utilities and phone carriers. The billing period, due date and
+/// usage are kept as metadata: either on the card or bank transaction that pays the bill, through
+/// the receipt store, or on a transaction of its own when an account is set for bills.
+pub struct UtilityBillParsingScheme {
+	pub account: Option<String>,
+	pub receipts: Option<Arc<ReceiptStore>>,
+}
+
+impl UtilityBillParsingScheme {
+	fn find_biller(&self, mail: &Mail) -> Option<&'static Biller> {
+		BILLERS.iter().find(|b| {
+			mail.from.contains(b.sender)
+				&& b.subject_keywords.iter().any(|k| mail.subject.contains(k))
+		})
+	}
+}
+
+#[async_trait::async_trait]
+impl EmailParsingScheme for UtilityBillParsingScheme {
+	fn can_parse(&self, mail: &Mail) -> bool {
+		(self.account.is_some() || self.receipts.is_some()) && self.find_biller(mail).is_some()
+	}
+
+	async fn parse(&self, mail: &Mail) -> Result<Vec<Transaction>, ErrorInterface> {
+		let biller = self.find_biller(mail).ok_or("Unknown biller")?;
+		let receipt = read_bill(biller, &mail.body)?;
+
+		if let Some(account) = &self.account {
+			let mut amount = receipt.total;
+			amount.set_sign_negative(true);
+
+			return Ok(vec![Transaction {
+				subject: Some(receipt.store),
+				datetime: receipt.datetime,
+				amount,
+				account: account.clone(),
+				category: None,
+				receipt: None,
+				metadata: receipt.metadata,
+			}]);
+		}
+
+		let receipts = self.receipts.as_ref().ok_or("No receipt store set")?;
+		receipts.put(&receipt).await?;
+
+		// the bill is recorded when it is paid
+		Ok(vec![])
+	}
+}
+
+/// Reads a bill into a receipt without items, dated at the end of the billing period since the
+/// payment for it cannot come earlier. The payment is matched up to the due date.
+fn read_bill(biller: &Biller, body: &str) -> Result<Receipt, ErrorInterface> {
+	let fields = parse_labeled_lines(body);
+	let find_field = |labels: &[&str]| labels.iter().find_map(|l| fields.get(*l));
+	let mut metadata = BTreeMap::new();
+
+	// Amount
+	let amount_string = find_field(biller.amount_labels).ok_or("No amount data found")?;
+	// notes such as （税込） may follow the amount
+	let amount_string = match amount_string.split_once('円') {
+		Some((amount, _)) => format!("{}円", amount),
+		None => amount_string.clone(),
+	};
+	let total = parse_jpy_amount(&amount_string).ok_or("Failed to parse amount")?;
+
+	// Billing period
+	let period_string = find_field(biller.period_labels).ok_or("No billing period found")?;
+	let (period_start, period_end) = match period_string.split_once(['～', '〜', '~']) {
+		Some((start, end)) => (parse_period_date(start)?, parse_period_date(end)?),
+		None => {
+			let month = parse_period_date(period_string)?;
+			(month.clone(), month)
+		}
+	};
+	metadata.insert(
+		"billing_period".to_owned(),
+		match period_start == period_end {
+			true => period_start.0.clone(),
+			false => format!("{}/{}", period_start.0, period_end.0),
+		},
+	);
+
+	// Due date
+	let mut due_date = None;
+	if let Some(due_date_string) = find_field(biller.due_date_labels) {
+		let (written, date) = parse_period_date(due_date_string)?;
+		metadata.insert("due_date".to_owned(), written);
+		due_date = Some(jst_midnight(date));
+	}
+
+	// Usage
+	if let Some(usage_string) = find_field(biller.usage_labels) {
+		let (usage, unit) = parse_usage(usage_string)?;
+		metadata.insert("usage".to_owned(), usage);
+		metadata.insert("usage_unit".to_owned(), unit);
+	}
+
+	let datetime = jst_midnight(period_end.1);
+
+	Ok(Receipt {
+		key: Receipt::make_key(biller.name, Some(&metadata["billing_period"]), &datetime),
+		store: biller.name.to_owned(),
+		card_subjects: biller
+			.payment_subjects
+			.iter()
+			.map(|s| s.to_string())
+			.collect(),
+		order_number: None,
+		datetime,
+		due_date,
+		total,
+		currency: "JPY".to_owned(),
+		items: vec![],
+		items_in_subject: false,
+		recorded_as_items: false,
+		metadata,
+	})
+}
+
+/// Parses a date such as `2025年1月8日`, or a month such as `2025年1月`, into the way it is
+/// written down in the metadata and the last day it stands for.
+fn parse_period_date(text: &str) -> Result<(String, NaiveDate), ErrorInterface> {
+	let text = text.trim();
+	if let Some(date) = DATE_FORMATS
+		.iter()
+		.find_map(|f| NaiveDate::parse_from_str(text, f).ok())
+	{
+		return Ok((date.format("%Y-%m-%d").to_string(), date));
+	}
+
+	let first_day = NaiveDate::parse_from_str(&format!("{}1日", text), "%Y年%m月%d日")
+		.or(NaiveDate::parse_from_str(
+			&format!("{}/1", text),
+			"%Y/%m/%d",
+		))
+		.map_err(|_| format!("Unknown date format: {}", text))?;
+	let last_day = first_day
+		.checked_add_months(chrono::Months::new(1))
+		.and_then(|d| d.pred_opt())
+		.ok_or("Date out of range")?;
+
+	Ok((first_day.format("%Y-%m").to_string(), last_day))
+}
+
+fn jst_midnight(date: NaiveDate) -> DateTime<Utc> {
+	chrono_tz::Asia::Tokyo
+		.from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+		.unwrap()
+		.with_timezone(&Utc)
+}
+
+/// Parses a usage figure such as `312kWh`, `23.4GB` or `32m³` into the number and the unit.
+fn parse_usage(text: &str) -> Result<(String, String), ErrorInterface> {
+	let captures = Regex::new(r"([0-9][0-9.,]*)\s*(kWh|GB|MB|m³|m3|㎥)")?
+		.captures(text)
+		.ok_or(format!("Unknown usage format: {}", text))?;
+	let unit = match &captures[2] {
+		"m3" | "㎥" => "m³",
+		unit => unit,
+	};
+
+	Ok((captures[1].replace(",", ""), unit.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeMap;
+	use std::sync::Arc;
+
+	use chrono::Duration;
+	use rust_decimal::Decimal;
+
+	use crate::mail::Mail;
+	use crate::mail::parsers::EmailParsingScheme;
+	use crate::receipt::ReceiptStore;
+	use crate::transaction::Transaction;
+
+	use super::UtilityBillParsingScheme;
+
+	#[tokio::test]
+	async fn bills_keep_period_due_date_and_usage() {
+		let scheme = UtilityBillParsingScheme {
+			account: Some("Bills".into()),
+			receipts: None,
+		};
+		let cases = [
+			(
+				"はぴeみる電 <info@kepco.co.jp>",
+				"【関西電力】電気料金のお知らせ",
+				include_str!("fixtures/kepco_bill.txt"),
+				"関西電力 -9876 2025-02-05 15:00:00 UTC billing_period=2025-01-08/2025-02-06; due_date=2025-03-10; usage=312; usage_unit=kWh",
+			),
+			(
+				"楽天モバイル <info@mobile.rakuten.co.jp>",
+				"【楽天モバイル】ご請求金額確定のお知らせ",
+				include_str!("fixtures/rakuten_mobile_bill.txt"),
+				"楽天モバイル -3278 2025-01-30 15:00:00 UTC billing_period=2025-01; due_date=2025-02-27; usage=23.4; usage_unit=GB",
+			),
+			(
+				"神戸市水道局 <suido-info@water.city.kobe.lg.jp>",
+				"【神戸市水道局】水道料金・下水道使用料のお知らせ",
+				include_str!("fixtures/kobe_water_bill.txt"),
+				"神戸市水道局 -8910 2025-01-20 15:00:00 UTC billing_period=2024-11-20/2025-01-21; due_date=2025-02-28; usage=32; usage_unit=m³",
+			),
+		];
+
+		for (from, subject, body, expected) in cases {
+			let mut mail = Mail::create_test_mail();
+			mail.from = from.into();
+			mail.subject = subject.into();
+			mail.body = body.into();
+			assert!(scheme.can_parse(&mail));
+
+			let transactions = scheme.parse(&mail).await.unwrap();
+			assert_eq!(1, transactions.len());
+			let t = &transactions[0];
+			assert_eq!(
+				expected,
+				format!(
+					"{} {} {} {}",
+					t.subject.as_deref().unwrap(),
+					t.amount,
+					t.datetime,
+					t.notes().unwrap()
+				)
+			);
+		}
+	}
+
+	#[tokio::test]
+	async fn payment_on_the_due_date_gets_the_bill() {
+		let directory =
+			std::env::temp_dir().join(format!("negi-utility-bill-test-{}", std::process::id()));
+		let receipts = Arc::new(ReceiptStore {
+			directory: directory.clone(),
+			match_window: Duration::days(14),
+		});
+		let scheme = UtilityBillParsingScheme {
+			account: None,
+			receipts: Some(receipts.clone()),
+		};
+
+		let mut mail = Mail::create_test_mail();
+		mail.from = "はぴeみる電 <info@kepco.co.jp>".into();
+		mail.subject = "【関西電力】電気料金のお知らせ".into();
+		mail.body = include_str!("fixtures/kepco_bill.txt").into();
+		assert!(scheme.parse(&mail).await.unwrap().is_empty());
+
+		// more than a month after the end of the billing period
+		let mut payment = Transaction {
+			subject: Some("関西電力".into()),
+			datetime: "2025-03-10T01:00:00Z".parse().unwrap(),
+			amount: Decimal::from(-9876),
+			account: "Rakuten".into(),
+			category: None,
+			receipt: None,
+			metadata: BTreeMap::new(),
+		};
+		assert_eq!(1, receipts.enrich(vec![&mut payment]).await.unwrap().len());
+		assert_eq!("2025-03-10", payment.metadata["due_date"]);
+		assert_eq!("312", payment.metadata["usage"]);
+
+		std::fs::remove_dir_all(directory).unwrap();
+	}
+
+	#[tokio::test]
+	async fn bank_debits_match_in_either_kana_width() {
+		let directory = std::env::temp_dir().join(format!(
+			"negi-utility-bill-kana-test-{}",
+			std::process::id()
+		));
+		let receipts = Arc::new(ReceiptStore {
+			directory: directory.clone(),
+			match_window: Duration::days(14),
+		});
+		let scheme = UtilityBillParsingScheme {
+			account: None,
+			receipts: Some(receipts.clone()),
+		};
+
+		let mut mail = Mail::create_test_mail();
+		mail.from = "はぴeみる電 <info@kepco.co.jp>".into();
+		mail.subject = "【関西電力】電気料金のお知らせ".into();
+		mail.body = include_str!("fixtures/kepco_bill.txt").into();
+
+		for subject in ["ｶﾝｻｲﾃﾞﾝﾘﾖｸ", "カンサイデンリヨク", "ｶﾝｻｲﾃﾞﾝﾘﾖｸ(ｶ"]
+		{
+			assert!(scheme.parse(&mail).await.unwrap().is_empty());
+
+			let mut payment = Transaction {
+				subject: Some(subject.into()),
+				datetime: "2025-03-10T01:00:00Z".parse().unwrap(),
+				amount: Decimal::from(-9876),
+				account: "Yuucho".into(),
+				category: None,
+				receipt: None,
+				metadata: BTreeMap::new(),
+			};
+			assert_eq!(1, receipts.enrich(vec![&mut payment]).await.unwrap().len());
+		}
+
+		std::fs::remove_dir_all(directory).unwrap();
+	}
+}
@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+
+use crate::ErrorInterface;
+use crate::mail::{
+	Mail,
+	parsers::{parse_jpy_amount, parse_labeled_lines, parse_local_datetime},
+};
+
+use super::{EmailParsingScheme, Transaction};
+
+const DATETIME_FORMATS: &[&str] = &[
+	"%Y年%m月%d日 %H:%M:%S",
+	"%Y年%m月%d日 %H:%M",
+	"%Y/%m/%d %H:%M:%S",
+	"%Y/%m/%d %H:%M",
+];
+
+pub struct YuuchoDebitParsingScheme {
+	pub account: String,
+}
+
+impl YuuchoDebitParsingScheme {
+	fn is_refund(&self, mail: &Mail) -> bool {
+		mail.subject.contains("取消")
+			|| mail.subject.contains("返金")
+			|| mail.body.contains("取消金額")
+			|| mail.body.contains("返金金額")
+	}
+}
+
+#[async_trait::async_trait]
+impl EmailParsingScheme for YuuchoDebitParsingScheme {
+	fn can_parse(&self, mail: &Mail) -> bool {
+		mail.from.contains("jp-bank.japanpost.jp") && mail.subject.contains("ゆうちょデビット")
+	}
+
+	async fn parse(&self, mail: &Mail) -> Result<Vec<Transaction>, ErrorInterface> {
+		let fields = parse_labeled_lines(&mail.body);
+		let find_field = |labels: &[&str]| labels.iter().find_map(|l| fields.get(*l));
+		let is_refund = self.is_refund(mail);
+
+		// Amount
+		let amount_string =
+			find_field(&["ご利用金額", "取消金額", "返金金額"]).ok_or("No amount data found")?;
+		let mut amount = parse_jpy_amount(amount_string).ok_or("Failed to parse amount")?;
+		amount.set_sign_negative(!is_refund);
+
+		// Datetime
+		let datetime_string =
+			find_field(&["ご利用日時", "取消日時", "返金日時"]).ok_or("No datetime data found")?;
+		let datetime =
+			parse_local_datetime(datetime_string, DATETIME_FORMATS, chrono_tz::Asia::Tokyo)?;
+
+		// Subject
+		let subject = find_field(&["ご利用店舗", "ご利用先", "加盟店名"])
+			.ok_or("No subject data found")?
+			.clone();
+
+		let mut metadata = BTreeMap::new();
+		if is_refund {
+			metadata.insert("kind".to_owned(), "refund".to_owned());
+		}
+		if let Some(approval_number) = find_field(&["承認番号"]) {
+			metadata.insert("approval_number".to_owned(), approval_number.clone());
+		}
+
+		Ok(vec![Transaction {
+			subject: Some(subject),
+			datetime,
+			amount,
+			account: self.account.clone(),
+			category: None,
+			receipt: None,
+			metadata,
+		}])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::mail::Mail;
+	use crate::mail::parsers::EmailParsingScheme;
+
+	use super::YuuchoDebitParsingScheme;
+
+	#[tokio::test]
+	async fn parses_usage_and_cancellation_notices() {
+		let scheme = YuuchoDebitParsingScheme {
+			account: "Yuucho".into(),
+		};
+
+		let mut mail = Mail::create_test_mail();
+		mail.from = "ゆうちょ銀行 <yucho-debit@jp-bank.japanpost.jp>".into();
+		mail.subject = "【ゆうちょデビット】ご利用のお知らせ".into();
+		mail.body = include_str!("fixtures/yuucho_debit.txt").into();
+		assert!(scheme.can_parse(&mail));
+
+		let transactions = scheme.parse(&mail).await.unwrap();
+		assert_eq!(Some("イオン 神戸南店".to_owned()), transactions[0].subject);
+		assert_eq!("-3456", transactions[0].amount.to_string());
+		assert_eq!(
+			"2025-01-15 03:34:56 UTC",
+			transactions[0].datetime.to_string()
+		);
+		assert_eq!("123456", transactions[0].metadata["approval_number"]);
+
+		mail.subject = "【ゆうちょデビット】ご利用取消のお知らせ".into();
+		mail.body = include_str!("fixtures/yuucho_debit_cancel.txt").into();
+		let transactions = scheme.parse(&mail).await.unwrap();
+		assert_eq!("3456", transactions[0].amount.to_string());
+		assert_eq!("refund", transactions[0].metadata["kind"]);
+	}
+}
@@ -1,21 +1,24 @@
+use std::env;
 use std::path::PathBuf;
 
 #[cfg(debug_assertions)]
 use log::debug;
 
+use chrono::{DateTime, Utc};
 use log::info;
-use mailparse::{ParsedMail, body::Body, parse_mail};
+use mailparse::{DispositionType, ParsedMail, body::Body, dateparse, parse_mail};
+use regex::Regex;
 use tokio::fs;
 
 use crate::ErrorInterface;
 
-use super::{Mail, RawMail, get_maildir_cur_path, get_maildir_new_path};
+use super::{Attachment, Mail, RawMail, address_of, get_maildir_cur_path, get_maildir_new_path};
 
 pub async fn read_emails() -> Result<Vec<Mail>, ErrorInterface> {
 	let maildir_path: Vec<PathBuf> = vec![get_maildir_new_path()?, get_maildir_cur_path()?];
 
 	let raw_mails = walk_directory(&maildir_path).await?;
-	let parsed_mails = parse_raw_emails(raw_mails);
+	let parsed_mails = parse_raw_emails(raw_mails, &read_trusted_forwarders());
 
 	info!("{} emails found", parsed_mails.len());
 
@@ -48,7 +51,27 @@ async fn walk_directory(maildir_paths: &Vec<PathBuf>) -> Result<Vec<RawMail>, Er
 	Ok(raw_mails)
 }
 
-fn parse_raw_emails(mails: Vec<RawMail>) -> Vec<Mail> {
+/// Reads the addresses, or `@domain`s, in `TRUSTED_FORWARDERS`.
+fn read_trusted_forwarders() -> Vec<String> {
+	env::var("TRUSTED_FORWARDERS")
+		.unwrap_or_default()
+		.split(",")
+		.map(|s| s.trim().to_lowercase())
+		.filter(|s| !s.is_empty())
+		.collect()
+}
+
+/// Whether the sender is one whose forwarded messages are read as the original sender's. Anyone
+/// can write a forward, so only the configured forwarders are trusted with it.
+fn is_trusted_forwarder(from: &str, trusted_forwarders: &[String]) -> bool {
+	let address = address_of(from);
+	trusted_forwarders.iter().any(|t| match t.starts_with('@') {
+		true => address.ends_with(t.as_str()),
+		false => address == *t,
+	})
+}
+
+fn parse_raw_emails(mails: Vec<RawMail>, trusted_forwarders: &[String]) -> Vec<Mail> {
 	mails
 		.into_iter()
 		.filter_map(|raw_mail| {
@@ -58,40 +81,361 @@ fn parse_raw_emails(mails: Vec<RawMail>) -> Vec<Mail> {
 			}
 			let parsed: ParsedMail<'_> = parsed.unwrap();
 
-			// Parse subject and from fields from header
-			let mut subject = String::from("");
-			let mut from = String::from("");
-			let headers = parsed.get_headers();
-			for header in headers {
-				if header.get_key().to_lowercase() == "subject" {
-					subject = String::from(header.get_value());
-				} else if header.get_key().to_lowercase() == "from" {
-					from = String::from(header.get_value());
-				}
+			Some(read_mail(raw_mail.file_path, &parsed, trusted_forwarders))
+		})
+		.collect::<Vec<Mail>>()
+}
+
+/// Reads a parsed mail. A message forwarded by a trusted forwarder, either attached or quoted in
+/// the body, is read in its place so parsers see its original sender.
+fn read_mail(file_path: PathBuf, parsed: &ParsedMail<'_>, trusted_forwarders: &[String]) -> Mail {
+	// Parse subject, from and date fields from header
+	let mut subject = String::from("");
+	let mut from = String::from("");
+	let mut date = None;
+	let headers = parsed.get_headers();
+	for header in headers {
+		match header.get_key().to_lowercase().as_str() {
+			"subject" => subject = header.get_value(),
+			"from" => from = header.get_value(),
+			"date" => date = parse_date(&header.get_value()),
+			_ => {}
+		}
+	}
+
+	let trusted = is_trusted_forwarder(&from, trusted_forwarders);
+
+	if trusted
+		&& let Some(attached) = parsed
+			.parts()
+			.find(|p| p.ctype.mimetype == "message/rfc822")
+			.and_then(|p| p.get_body_raw().ok())
+		&& let Ok(attached) = parse_mail(&attached)
+	{
+		// forwards of forwards are not unwrapped any further
+		let mut mail = read_mail(file_path, &attached, &[]);
+		mail.forwarded_by = Some(from);
+		mail.forward_note =
+			Some(read_body(parsed, true).trim().to_owned()).filter(|n| !n.is_empty());
+		// files sent along with the forwarded message
+		mail.attachments.extend(read_attachments(parsed));
+		return mail;
+	}
+
+	let mail = Mail {
+		file_path,
+		from,
+		subject,
+		body: read_body(parsed, false),
+		date,
+		forwarded_by: None,
+		forward_note: None,
+		attachments: read_attachments(parsed),
+	};
+
+	match trusted {
+		true => unwrap_inline_forward(&mail).unwrap_or(mail),
+		false => mail,
+	}
+}
+
+/// Reads the text of the mail, leaving out attachments, and attached messages if asked to.
+fn read_body(parsed: &ParsedMail<'_>, skip_attached_messages: bool) -> String {
+	let mut body: String = String::from("");
+	for part in parsed.parts().filter(|p| {
+		attachment_filename(p).is_none()
+			&& !(skip_attached_messages && p.ctype.mimetype == "message/rfc822")
+	}) {
+		match part.get_body_encoded() {
+			Body::Base64(b) | Body::QuotedPrintable(b) => {
+				let decoded = b.get_decoded_as_string().unwrap_or(String::from(""));
+				body.push_str(decoded.as_str());
 			}
+			Body::SevenBit(b) | Body::EightBit(b) => {
+				let decoded = b.get_as_string().unwrap_or(String::from(""));
+				body.push_str(decoded.as_str());
+			}
+			_ => {}
+		}
+	}
 
-			// Parse body into String
-			let mut body: String = String::from("");
-			for part in parsed.parts() {
-				match part.get_body_encoded() {
-					Body::Base64(b) | Body::QuotedPrintable(b) => {
-						let decoded = b.get_decoded_as_string().unwrap_or(String::from(""));
-						body.push_str(decoded.as_str());
-					}
-					Body::SevenBit(b) | Body::EightBit(b) => {
-						let decoded = b.get_as_string().unwrap_or(String::from(""));
-						body.push_str(decoded.as_str());
+	body
+}
+
+/// Collects the PDF and image files attached to the mail.
+fn read_attachments(parsed: &ParsedMail<'_>) -> Vec<Attachment> {
+	parsed
+		.parts()
+		.filter_map(|part| {
+			let filename = attachment_filename(part)?;
+			let contents = part.get_body_raw().ok()?;
+
+			Some(Attachment {
+				filename,
+				content_type: part.ctype.mimetype.clone(),
+				contents,
+			})
+		})
+		.collect()
+}
+
+/// The file name of a part that is a PDF or an image attachment, or `None` for any other part.
+fn attachment_filename(part: &ParsedMail<'_>) -> Option<String> {
+	let mimetype = part.ctype.mimetype.as_str();
+	if mimetype != "application/pdf" && !mimetype.starts_with("image/") {
+		return None;
+	}
+
+	let disposition = part.get_content_disposition();
+	let filename = disposition
+		.params
+		.get("filename")
+		.or(part.ctype.params.get("name"));
+	match (disposition.disposition, filename) {
+		(DispositionType::Attachment, Some(filename)) => Some(filename.clone()),
+		(DispositionType::Attachment, None) => Some(match mimetype {
+			"application/pdf" => "receipt.pdf".to_owned(),
+			_ => format!("receipt.{}", mimetype.trim_start_matches("image/")),
+		}),
+		// inline images are logos and the like, but a named PDF is kept however it is sent
+		(_, Some(filename)) if mimetype == "application/pdf" => Some(filename.clone()),
+		_ => None,
+	}
+}
+
+/// Finds a message forwarded inline, such as
+///
+/// ```text
+/// ---------- Forwarded message ---------
+/// From: Bank <info@bank.example>
+/// Date: Mon, 20 Jan 2025 10:15:00 +0900
+/// Subject: Payment notice
+///
+/// ...
+/// ```
+///
+/// and returns it as a mail of its own. Reply quotes such as Outlook's "Original Message" are
+/// not forwards and are left alone.
+fn unwrap_inline_forward(mail: &Mail) -> Option<Mail> {
+	let marker = Regex::new(
+		r"(?m)^[ \t>]*(?:-+ *(?:Forwarded message|転送メッセージ|転送されたメッセージ) *-+|Begin forwarded message:)[ \t]*\r?$",
+	)
+	.unwrap();
+	let found = marker.find(&mail.body)?;
+	let forwarded = &mail.body[found.end()..];
+	let note = mail.body[..found.start()].trim();
+
+	let mut from: Option<String> = None;
+	let mut subject = String::new();
+	let mut date = None;
+	let mut body_start = forwarded.len();
+	let mut offset = 0;
+	let mut last_key = String::new();
+	for line in forwarded.split_inclusive('\n') {
+		offset += line.len();
+		let trimmed = line.trim().trim_start_matches('>').trim();
+		if trimmed.is_empty() {
+			// the headers end at the first blank line after them
+			if from.is_some() {
+				body_start = offset;
+				break;
+			}
+			continue;
+		}
+
+		// a line without a colon continues the header above, such as a folded To: or Subject:
+		let Some((key, value)) = trimmed.split_once([':', '：']) else {
+			match last_key.as_str() {
+				"From" | "差出人" | "送信者" => {
+					if let Some(from) = &mut from {
+						from.push(' ');
+						from.push_str(trimmed);
 					}
-					_ => {}
 				}
+				"Subject" | "件名" => {
+					subject.push(' ');
+					subject.push_str(trimmed);
+				}
+				_ => {}
 			}
+			continue;
+		};
+		let value = value.trim().to_owned();
+		last_key = key.trim().to_owned();
+		match last_key.as_str() {
+			"From" | "差出人" | "送信者" => from = Some(value),
+			"Subject" | "件名" => subject = value,
+			"Date" | "Sent" | "日付" | "送信日時" => date = parse_date(&value),
+			_ => {}
+		}
+	}
 
-			Some(Mail {
-				file_path: raw_mail.file_path,
-				from,
-				subject,
-				body,
-			})
-		})
-		.collect::<Vec<Mail>>()
+	Some(Mail {
+		file_path: mail.file_path.clone(),
+		from: from?,
+		subject,
+		body: forwarded[body_start..].to_owned(),
+		date,
+		forwarded_by: Some(mail.from.clone()),
+		forward_note: Some(note.to_owned()).filter(|n| !n.is_empty()),
+		attachments: mail.attachments.clone(),
+	})
+}
+
+/// Parses a mail date. Dates without a time zone, such as Gmail's `Mon, Jan 20, 2025 at 10:15
+/// AM` in forwarded messages, are not read.
+fn parse_date(text: &str) -> Option<DateTime<Utc>> {
+	DateTime::from_timestamp(dateparse(text).ok()?, 0)
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::mail::RawMail;
+
+	use super::parse_raw_emails;
+
+	fn trusted_forwarders() -> Vec<String> {
+		vec!["hana@family.example".to_owned()]
+	}
+
+	#[test]
+	fn attached_message_is_read_with_its_original_sender() {
+		let contents = concat!(
+			"From: Hana <hana@family.example>\r\n",
+			"Subject: Fwd: ゆうちょデビットご利用のお知らせ\r\n",
+			"Date: Tue, 21 Jan 2025 08:00:00 +0900\r\n",
+			"Content-Type: multipart/mixed; boundary=outer\r\n",
+			"\r\n",
+			"--outer\r\n",
+			"Content-Type: text/plain; charset=utf-8\r\n",
+			"\r\n",
+			"FYI\r\n",
+			"--outer\r\n",
+			"Content-Type: message/rfc822\r\n",
+			"\r\n",
+			"From: ゆうちょ銀行 <debit@jp-bank.japanpost.jp>\r\n",
+			"Subject: ゆうちょデビットご利用のお知らせ\r\n",
+			"Date: Mon, 20 Jan 2025 10:15:00 +0900\r\n",
+			"Content-Type: text/plain; charset=utf-8\r\n",
+			"\r\n",
+			"ご利用金額：1,500円\r\n",
+			"--outer--\r\n",
+		);
+
+		let mails = parse_raw_emails(
+			vec![RawMail {
+				file_path: "/tmp/fake-path".into(),
+				contents: contents.as_bytes().to_vec(),
+			}],
+			&trusted_forwarders(),
+		);
+		assert_eq!(1, mails.len());
+		let mail = &mails[0];
+		assert_eq!("ゆうちょ銀行 <debit@jp-bank.japanpost.jp>", mail.from);
+		assert_eq!("ゆうちょデビットご利用のお知らせ", mail.subject);
+		assert_eq!("2025-01-20T01:15:00+00:00", mail.date.unwrap().to_rfc3339());
+		assert_eq!(
+			Some("Hana <hana@family.example>"),
+			mail.forwarded_by.as_deref()
+		);
+		assert_eq!("ご利用金額：1,500円\r\n", mail.body);
+		assert_eq!(Some("FYI"), mail.forward_note.as_deref());
+
+		// anyone else's attached message is not read as the original sender's
+		let mails = parse_raw_emails(
+			vec![RawMail {
+				file_path: "/tmp/fake-path".into(),
+				contents: contents.as_bytes().to_vec(),
+			}],
+			&["@bank.example".to_owned()],
+		);
+		assert_eq!("Hana <hana@family.example>", mails[0].from);
+		assert!(mails[0].forwarded_by.is_none());
+	}
+
+	#[test]
+	fn inline_forward_is_read_with_its_original_sender() {
+		let contents = concat!(
+			"From: Hana <hana@family.example>\r\n",
+			"Subject: Fwd: Transaksi QRIS\r\n",
+			"Content-Type: text/plain; charset=utf-8\r\n",
+			"\r\n",
+			"Ini struknya\r\n",
+			"\r\n",
+			"---------- Forwarded message ---------\r\n",
+			"From: OCBC <notifikasi@ocbc.id>\r\n",
+			"Date: Mon, Jan 20, 2025 at 10:15 AM\r\n",
+			"Subject: Transaksi QRIS Berhasil\r\n",
+			"To: <hana@family.example>\r\n",
+			"\r\n",
+			"Nominal: IDR 57.300\r\n",
+		);
+
+		let read = |contents: &str, trusted_forwarders: &[String]| {
+			parse_raw_emails(
+				vec![RawMail {
+					file_path: "/tmp/fake-path".into(),
+					contents: contents.as_bytes().to_vec(),
+				}],
+				trusted_forwarders,
+			)
+			.remove(0)
+		};
+
+		let mail = read(contents, &trusted_forwarders());
+		assert_eq!("OCBC <notifikasi@ocbc.id>", mail.from);
+		assert_eq!("Transaksi QRIS Berhasil", mail.subject);
+		assert!(mail.date.is_none());
+		assert_eq!(
+			Some("Hana <hana@family.example>"),
+			mail.forwarded_by.as_deref()
+		);
+		assert_eq!("Nominal: IDR 57.300\r\n", mail.body);
+		assert_eq!(Some("Ini struknya"), mail.forward_note.as_deref());
+
+		let mail = read(contents, &[]);
+		assert_eq!("Hana <hana@family.example>", mail.from);
+		assert!(mail.forwarded_by.is_none());
+
+		// a reply quote is not a forward
+		let reply = contents.replace(
+			"---------- Forwarded message ---------",
+			"-----Original Message-----",
+		);
+		let mail = read(&reply, &trusted_forwarders());
+		assert_eq!("Hana <hana@family.example>", mail.from);
+		assert!(mail.forwarded_by.is_none());
+	}
+
+	#[test]
+	fn inline_forward_with_folded_headers_keeps_its_body() {
+		let contents = concat!(
+			"From: Hana <hana@family.example>\r\n",
+			"Subject: Fwd: Transaksi QRIS\r\n",
+			"Content-Type: text/plain; charset=utf-8\r\n",
+			"\r\n",
+			"---------- Forwarded message ---------\r\n",
+			"From: OCBC\r\n",
+			" <notifikasi@ocbc.id>\r\n",
+			"Subject: Transaksi QRIS\r\n",
+			"  Berhasil\r\n",
+			"To: <hana@family.example>,\r\n",
+			"\t<budi@family.example>\r\n",
+			"budi.kantor@office.example\r\n",
+			"\r\n",
+			"Nominal: IDR 57.300\r\n",
+		);
+
+		let mail = parse_raw_emails(
+			vec![RawMail {
+				file_path: "/tmp/fake-path".into(),
+				contents: contents.as_bytes().to_vec(),
+			}],
+			&trusted_forwarders(),
+		)
+		.remove(0);
+		assert_eq!("OCBC <notifikasi@ocbc.id>", mail.from);
+		assert_eq!("Transaksi QRIS Berhasil", mail.subject);
+		assert_eq!("Nominal: IDR 57.300\r\n", mail.body);
+	}
 }
@@ -12,6 +12,8 @@ use crate::{
 pub struct DummyClient {
 	injected_response_code: Option<u16>,
 	injected_response_body: Option<String>,
+	queued_responses: std::sync::Mutex<std::collections::VecDeque<(u16, String)>>,
+	requests: std::sync::Mutex<Vec<serde_json::Value>>,
 }
 
 #[cfg(test)]
@@ -24,12 +26,30 @@ impl DummyClient {
 		self.injected_response_code = Some(code);
 		self.injected_response_body = Some(body);
 	}
+
+	/// Queues a response that is returned once, before falling back to the injected response.
+	pub fn queue_response(&mut self, code: u16, body: String) {
+		self.queued_responses
+			.lock()
+			.unwrap()
+			.push_back((code, body));
+	}
+
+	pub fn requests(&self) -> Vec<serde_json::Value> {
+		self.requests.lock().unwrap().clone()
+	}
 }
 
 #[cfg(test)]
 #[async_trait::async_trait]
 impl Client for DummyClient {
-	async fn post(&self, _: ClientRequest) -> Result<ClientResponse, ErrorInterface> {
+	async fn post(&self, request: ClientRequest) -> Result<ClientResponse, ErrorInterface> {
+		self.requests.lock().unwrap().push(request.body_json);
+
+		if let Some((code, body)) = self.queued_responses.lock().unwrap().pop_front() {
+			return Ok(ClientResponse { code, body });
+		}
+
 		Ok(ClientResponse {
 			code: self.injected_response_code.unwrap_or(200),
 			body: self
@@ -0,0 +1,339 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Duration, Utc};
+use log::{info, warn};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::ErrorInterface;
+use crate::transaction::Transaction;
+
+const DEFAULT_MATCH_DAYS: i64 = 14;
+/// Metadata key marking a card transaction whose receipt was recorded item by item.
+const RECORDED_AS_ITEMS: &str = "recorded_as_items";
+
+/// Whether the card transaction was matched with a receipt whose items are transactions of their
+/// own, so it should not be saved.
+pub fn is_recorded_as_items(transaction: &Transaction) -> bool {
+	transaction.metadata.contains_key(RECORDED_AS_ITEMS)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReceiptItem {
+	pub name: String,
+	pub quantity: u32,
+	/// Price of one unit.
+	pub price: Decimal,
+}
+
+/// What a store says was bought, kept until the card transaction for it shows up.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Receipt {
+	pub key: String,
+	pub store: String,
+	/// Parts of the subject the card transaction is expected to have, e.g. `AMAZON`.
+	pub card_subjects: Vec<String>,
+	pub order_number: Option<String>,
+	pub datetime: DateTime<Utc>,
+	/// When a bill is due. Its payment can come weeks after `datetime`, so matching and expiry
+	/// count from this instead when it is set.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub due_date: Option<DateTime<Utc>>,
+	/// The amount charged, as a positive number.
+	pub total: Decimal,
+	pub currency: String,
+	pub items: Vec<ReceiptItem>,
+	/// Whether the items are added to the card transaction's subject, instead of only noting them
+	/// down in the metadata. The card's own subject is kept in front, so categorizing by it still
+	/// works.
+	#[serde(alias = "replace_subject")]
+	pub items_in_subject: bool,
+	/// Whether the items were written down as transactions of their own already. The card
+	/// transaction for them would count the spending twice, so it is left out instead.
+	#[serde(default)]
+	pub recorded_as_items: bool,
+	/// Anything else to note down on the card transaction, such as a bill's usage.
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub metadata: BTreeMap<String, String>,
+}
+
+impl Receipt {
+	pub fn make_key(store: &str, order_number: Option<&str>, datetime: &DateTime<Utc>) -> String {
+		let mut hasher = Sha256::new();
+		hasher.update(store.as_bytes());
+		hasher.update([0]);
+		match order_number {
+			Some(order_number) => hasher.update(order_number.as_bytes()),
+			None => hasher.update(datetime.to_rfc3339().as_bytes()),
+		}
+		format!("{:x}", hasher.finalize())[..16].to_owned()
+	}
+
+	/// The item names, with the quantity for items bought more than once.
+	pub fn item_summary(&self) -> String {
+		self.items
+			.iter()
+			.map(|i| match i.quantity {
+				1 => i.name.clone(),
+				quantity => format!("{} x{}", i.name, quantity),
+			})
+			.collect::<Vec<String>>()
+			.join("; ")
+	}
+
+	fn matches(&self, transaction: &Transaction, match_window: Duration) -> bool {
+		// banks write the same name in half-width or full-width kana and letters
+		let normalize = |text: &str| text.nfkc().collect::<String>().to_uppercase();
+		let subject = normalize(transaction.subject.as_deref().unwrap_or_default());
+		if !self
+			.card_subjects
+			.iter()
+			.any(|s| subject.contains(&normalize(s)))
+		{
+			return false;
+		}
+
+		// the card may have been charged in the receipt's currency or in yen
+		let same_amount = match transaction.metadata.get("original_currency") {
+			Some(currency) if *currency == self.currency => transaction
+				.metadata
+				.get("original_amount")
+				.and_then(|a| Decimal::from_str_exact(a).ok())
+				.is_some_and(|a| a == self.total),
+			_ => self.currency == "JPY" && transaction.amount == -self.total,
+		};
+
+		same_amount
+			&& transaction.datetime >= self.datetime - Duration::days(1)
+			&& transaction.datetime <= self.last_match_datetime(match_window)
+	}
+
+	/// The latest a transaction can be dated and still be matched with this receipt.
+	fn last_match_datetime(&self, match_window: Duration) -> DateTime<Utc> {
+		self.due_date.unwrap_or(self.datetime) + match_window
+	}
+
+	fn apply_to(&self, transaction: &mut Transaction) {
+		if self.recorded_as_items {
+			transaction.metadata.insert(
+				RECORDED_AS_ITEMS.to_owned(),
+				self.order_number.clone().unwrap_or(self.key.clone()),
+			);
+			return;
+		}
+
+		let summary = self.item_summary();
+		if let Some(order_number) = &self.order_number {
+			transaction
+				.metadata
+				.insert("order_number".to_owned(), order_number.clone());
+		}
+		if !summary.is_empty() {
+			transaction
+				.metadata
+				.insert("items".to_owned(), summary.clone());
+		}
+		transaction.metadata.extend(self.metadata.clone());
+
+		if self.items_in_subject && !summary.is_empty() {
+			let subject = transaction.subject.as_deref().unwrap_or(&self.store);
+			transaction.subject = Some(format!("{}: {}", subject, summary));
+		}
+	}
+}
+
+/// Keeps receipts from order mails in a directory until they are matched with a card
+/// transaction, or until they are too old to be matched.
+pub struct ReceiptStore {
+	pub directory: PathBuf,
+	pub match_window: Duration,
+}
+
+impl ReceiptStore {
+	/// Builds the store from `RECEIPT_STORE_DIR` and `RECEIPT_MATCH_DAYS`. Receipts are not kept
+	/// if the directory is not set.
+	pub fn from_env() -> Option<Self> {
+		let directory = env::var("RECEIPT_STORE_DIR").ok()?;
+		if directory.is_empty() {
+			return None;
+		}
+
+		let match_days = env::var("RECEIPT_MATCH_DAYS")
+			.ok()
+			.and_then(|s| s.parse::<i64>().ok())
+			.unwrap_or(DEFAULT_MATCH_DAYS);
+
+		Some(Self {
+			directory: PathBuf::from(directory),
+			match_window: Duration::days(match_days),
+		})
+	}
+
+	pub async fn put(&self, receipt: &Receipt) -> Result<(), ErrorInterface> {
+		fs::create_dir_all(&self.directory).await?;
+		fs::write(
+			self.receipt_path(&receipt.key),
+			serde_json::to_string(receipt)?,
+		)
+		.await?;
+
+		Ok(())
+	}
+
+	pub async fn receipts(&self) -> Result<Vec<Receipt>, ErrorInterface> {
+		let mut receipts = vec![];
+		if !self.directory.exists() {
+			return Ok(receipts);
+		}
+
+		let mut entries = fs::read_dir(&self.directory).await?;
+		while let Some(entry) = entries.next_entry().await? {
+			if entry.path().extension().is_none_or(|e| e != "json") {
+				continue;
+			}
+
+			let contents = fs::read_to_string(entry.path()).await?;
+			match serde_json::from_str::<Receipt>(&contents) {
+				Ok(receipt) => receipts.push(receipt),
+				Err(e) => warn!("Skipping unreadable receipt {:?}: {}", entry.path(), e),
+			}
+		}
+		receipts.sort_by_key(|r| r.datetime);
+
+		Ok(receipts)
+	}
+
+	/// Adds the items of matching receipts to the transactions and returns the keys of the
+	/// receipts used. They are kept until `remove_settled` is called for them, so the receipts are
+	/// still there for the next run if the transactions could not be saved.
+	pub async fn enrich(
+		&self,
+		transactions: Vec<&mut Transaction>,
+	) -> Result<Vec<String>, ErrorInterface> {
+		let mut receipts = self.receipts().await?;
+		let mut matched = vec![];
+
+		for transaction in transactions {
+			let Some(index) = receipts
+				.iter()
+				.position(|r| r.matches(transaction, self.match_window))
+			else {
+				continue;
+			};
+
+			let receipt = receipts.remove(index);
+			receipt.apply_to(transaction);
+			matched.push(receipt.key);
+		}
+
+		Ok(matched)
+	}
+
+	/// Removes the matched receipts once their transactions are saved, and the receipts too old
+	/// to be matched anymore.
+	pub async fn remove_settled(&self, matched: &[String]) -> Result<(), ErrorInterface> {
+		for key in matched {
+			fs::remove_file(self.receipt_path(key)).await?;
+		}
+
+		for receipt in self.receipts().await? {
+			if receipt.last_match_datetime(self.match_window) < Utc::now() {
+				info!(
+					"Removing unmatched receipt from {} ({})",
+					receipt.store,
+					receipt.order_number.as_deref().unwrap_or(&receipt.key)
+				);
+				fs::remove_file(self.receipt_path(&receipt.key)).await?;
+			}
+		}
+
+		Ok(())
+	}
+
+	fn receipt_path(&self, key: &str) -> PathBuf {
+		self.directory.join(format!("{}.json", key))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeMap;
+
+	use chrono::{Duration, Utc};
+	use rust_decimal::Decimal;
+
+	use crate::transaction::Transaction;
+
+	use super::{Receipt, ReceiptItem, ReceiptStore};
+
+	#[tokio::test]
+	async fn matching_card_transaction_gets_the_items() {
+		let directory =
+			std::env::temp_dir().join(format!("negi-receipt-test-{}", std::process::id()));
+		let store = ReceiptStore {
+			directory: directory.clone(),
+			match_window: Duration::days(14),
+		};
+
+		let ordered_at = Utc::now() - Duration::days(2);
+		store
+			.put(&Receipt {
+				key: Receipt::make_key("Amazon.co.jp", Some("503-1"), &ordered_at),
+				store: "Amazon.co.jp".into(),
+				card_subjects: vec!["AMAZON".into()],
+				order_number: Some("503-1".into()),
+				datetime: ordered_at,
+				due_date: None,
+				total: Decimal::from(4046),
+				currency: "JPY".into(),
+				items: vec![
+					ReceiptItem {
+						name: "Anker PowerCore".into(),
+						quantity: 1,
+						price: Decimal::from(2990),
+					},
+					ReceiptItem {
+						name: "ワンピース 108".into(),
+						quantity: 2,
+						price: Decimal::from(528),
+					},
+				],
+				items_in_subject: false,
+				recorded_as_items: false,
+				metadata: BTreeMap::new(),
+			})
+			.await
+			.unwrap();
+
+		let make_transaction = |subject: &str, amount: i64| Transaction {
+			subject: Some(subject.into()),
+			datetime: Utc::now(),
+			amount: Decimal::from(amount),
+			account: "Rakuten".into(),
+			category: None,
+			receipt: None,
+			metadata: BTreeMap::new(),
+		};
+		let mut other = make_transaction("AMAZON.CO.JP", -1000);
+		let mut card = make_transaction("AMAZON.CO.JP", -4046);
+
+		let matched = store.enrich(vec![&mut other, &mut card]).await.unwrap();
+		assert_eq!(1, matched.len());
+		assert!(other.metadata.is_empty());
+		assert_eq!("503-1", card.metadata["order_number"]);
+		assert_eq!("Anker PowerCore; ワンピース 108 x2", card.metadata["items"]);
+		assert_eq!(Some("AMAZON.CO.JP".to_owned()), card.subject);
+
+		// kept until the transaction is saved
+		assert_eq!(1, store.receipts().await.unwrap().len());
+		store.remove_settled(&matched).await.unwrap();
+		assert!(store.receipts().await.unwrap().is_empty());
+
+		std::fs::remove_dir_all(directory).unwrap();
+	}
+}
@@ -1,6 +1,8 @@
 use std::env;
+use std::str::FromStr;
 
 use reqwest::Client;
+use rust_decimal::Decimal;
 use serde::Deserialize;
 
 use crate::{ErrorInterface, sheet::ValueRow};
@@ -12,7 +14,7 @@ struct ResponseFormat {
 
 pub async fn fetch_from_sheet(client: &Client) -> Result<Vec<ValueRow>, ErrorInterface> {
 	let spreadsheet_id = env::var("SPREADSHEET_ID")?;
-	let range = "Transactions!A2:F";
+	let range = "Transactions!A2:G";
 	let url = format!(
 		"https://sheets.googleapis.com/v4/spreadsheets/{}/values/{}?valueRenderOption=UNFORMATTED_VALUE",
 		spreadsheet_id, range
@@ -50,11 +52,21 @@ pub async fn fetch_from_sheet(client: &Client) -> Result<Vec<ValueRow>, ErrorInt
 				account: i[0].as_str().unwrap_or("").to_owned(),
 				subject: i[1].as_str().unwrap_or("").to_owned(),
 				date_value: i[2].as_f64().unwrap_or(0.0),
-				amount: i[3].as_i64().unwrap_or(0),
+				amount: read_amount(&i[3]),
+				currency: i[4].as_str().unwrap_or("").to_owned(),
 				category: i[5].as_str().unwrap_or("").to_owned(),
+				notes: i[6].as_str().unwrap_or("").to_owned(),
 			}
 		})
 		.collect();
 
 	Ok(values)
 }
+
+/// Reads an amount cell as a decimal, since amounts can have fractions, e.g. from IDR statements.
+pub(crate) fn read_amount(value: &serde_json::Value) -> Decimal {
+	value
+		.as_number()
+		.and_then(|n| Decimal::from_str(&n.to_string()).ok())
+		.unwrap_or_default()
+}
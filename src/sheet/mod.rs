@@ -1,3 +1,7 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
 pub mod auth;
@@ -7,7 +11,7 @@ pub mod write;
 #[derive(Serialize, Deserialize, Debug)]
 struct ValueRange {
 	pub range: String,
-	pub values: Vec<Vec<String>>, // Use Vec<Vec<String>> for writing
+	pub values: Vec<Vec<Option<String>>>, // None leaves the cell as it is
 }
 
 #[derive(Debug, Clone)]
@@ -16,8 +20,11 @@ pub struct ValueRow {
 	pub account: String,
 	pub subject: String,
 	pub date_value: f64,
-	pub amount: i64,
+	pub amount: Decimal,
+	/// Filled in by a formula from the account.
+	pub currency: String,
 	pub category: String,
+	pub notes: String,
 }
 
 impl ValueRow {
@@ -32,11 +39,44 @@ impl ValueRow {
 	pub fn subject_matches(&self, match_target: &str) -> bool {
 		return self.subject.to_lowercase().contains(&match_target.to_lowercase());
 	}
+
+	/// The `key=value` pairs written to the notes column from a transaction's metadata.
+	pub fn metadata(&self) -> BTreeMap<String, String> {
+		self.notes
+			.split("; ")
+			.filter_map(|pair| pair.split_once('='))
+			.map(|(key, value)| (key.trim().to_owned(), value.trim().to_owned()))
+			.collect()
+	}
+
+	pub fn datetime(&self) -> DateTime<Utc> {
+		serial_to_datetime(self.date_value)
+	}
+}
+
+/// Sheets counts dates as days since 1899-12-30, with the time of day as the fraction.
+fn serial_epoch() -> DateTime<Utc> {
+	NaiveDate::from_ymd_opt(1899, 12, 30)
+		.unwrap()
+		.and_hms_opt(0, 0, 0)
+		.unwrap()
+		.and_utc()
+}
+
+/// Turns a date serial number from the sheet into a datetime, to the second.
+pub fn serial_to_datetime(serial: f64) -> DateTime<Utc> {
+	serial_epoch() + Duration::seconds((serial * 86400.0).round() as i64)
+}
+
+pub fn datetime_to_serial(datetime: &DateTime<Utc>) -> f64 {
+	(*datetime - serial_epoch()).num_seconds() as f64 / 86400.0
 }
 
 #[cfg(test)]
 mod tests {
-	use super::ValueRow;
+	use rust_decimal::Decimal;
+
+	use super::{ValueRow, datetime_to_serial, serial_to_datetime};
 
 	#[test]
 	fn subject_matches_case_insensitive() {
@@ -45,10 +85,19 @@ mod tests {
 			account: "Bank".to_string(),
 			subject: "ちぇーストŌKaChIMaChI".to_string(),
 			date_value: 0.0,
-			amount: 1000,
+			amount: Decimal::from(1000),
+			currency: "".to_string(),
 			category: "".to_string(),
+			notes: "".to_string(),
 		};
 
 		assert!(row.subject_matches("ちぇーストōkachiMACHI"));
 	}
+
+	#[test]
+	fn serial_numbers_round_trip() {
+		let datetime = "2025-01-20T10:15:00Z".parse().unwrap();
+		assert_eq!(45677, datetime_to_serial(&datetime).trunc() as i64);
+		assert_eq!(datetime, serial_to_datetime(datetime_to_serial(&datetime)));
+	}
 }
@@ -1,7 +1,7 @@
 use std::env;
 
 use log::error;
-use reqwest::Client;
+use reqwest::{Client, Url};
 
 use crate::ErrorInterface;
 use crate::{sheet::ValueRange, transaction::Transaction};
@@ -13,7 +13,7 @@ pub async fn append_to_sheet(
 	transactions: Vec<Transaction>,
 ) -> Result<(), ErrorInterface> {
 	let spreadsheet_id = env::var("SPREADSHEET_ID")?;
-	let range = "Transactions!A:D";
+	let range = "Transactions!A:H";
 	let url = format!(
 		"https://sheets.googleapis.com/v4/spreadsheets/{}/values/{}:append?valueInputOption=USER_ENTERED&insertDataOption=INSERT_ROWS",
 		spreadsheet_id, range
@@ -25,15 +25,23 @@ pub async fn append_to_sheet(
 	};
 
 	for transaction in transactions {
+		let notes = transaction.notes();
+		let receipt = transaction.receipt.as_deref().map(receipt_link);
 		let row = vec![
-			transaction.account.trim().to_string(),
-			transaction
-				.subject
-				.unwrap_or("".to_string())
-				.trim()
-				.to_string(),
-			transaction.datetime.format("%Y-%m-%d %H:%M:%S").to_string(),
-			transaction.amount.to_string(),
+			Some(transaction.account.trim().to_string()),
+			Some(
+				transaction
+					.subject
+					.unwrap_or("".to_string())
+					.trim()
+					.to_string(),
+			),
+			Some(transaction.datetime.format("%Y-%m-%d %H:%M:%S").to_string()),
+			Some(transaction.amount.to_string()),
+			None,                 // currency is a formula
+			transaction.category, // left for marksman to fill in if None
+			notes,
+			receipt,
 		];
 		value_range.values.push(row);
 	}
@@ -50,6 +58,27 @@ pub async fn append_to_sheet(
 	}
 }
 
+/// Links an archived receipt through `RECEIPT_ARCHIVE_URL`, where the archive directory is
+/// served. Without it, only the receipt's path is written.
+fn receipt_link(path: &str) -> String {
+	let base_url = env::var("RECEIPT_ARCHIVE_URL").unwrap_or_default();
+	let Ok(mut url) = Url::parse(&base_url) else {
+		return path.to_owned();
+	};
+	let Ok(mut segments) = url.path_segments_mut() else {
+		return path.to_owned();
+	};
+	segments.pop_if_empty().extend(path.split('/'));
+	drop(segments);
+
+	let filename = path.rsplit('/').next().unwrap_or(path);
+	format!(
+		"=HYPERLINK(\"{}\", \"{}\")",
+		url.as_str().replace('"', "%22"),
+		filename.replace('"', "\"\"")
+	)
+}
+
 pub async fn mark_duplicates_in_sheet(
 	client: &Client,
 	rows: Vec<ValueRow>,
@@ -72,7 +101,7 @@ pub async fn mark_duplicates_in_sheet(
 			let url = make_url(&range);
 			let value_range = ValueRange {
 				range,
-				values: vec![vec![row.subject]],
+				values: vec![vec![Some(row.subject)]],
 			};
 
 			let response = client
@@ -98,7 +127,7 @@ pub async fn mark_duplicates_in_sheet(
 			let url = make_url(&range);
 			let value_range = ValueRange {
 				range,
-				values: vec![vec!["0".to_string()]],
+				values: vec![vec![Some("0".to_string())]],
 			};
 
 			let response = client
@@ -166,7 +195,7 @@ pub async fn set_categories_in_sheet(
 			let url = make_url(&range);
 			let value_range = ValueRange {
 				range,
-				values: vec![vec![row.category]],
+				values: vec![vec![Some(row.category)]],
 			};
 
 			let response = client
@@ -211,3 +240,57 @@ pub async fn set_categories_in_sheet(
 		}
 	}
 }
+
+/// Writes the status of each row, given by row number, to `column`.
+pub async fn set_statuses_in_sheet(
+	client: &Client,
+	column: &str,
+	statuses: Vec<(usize, String)>,
+) -> Result<(), ErrorInterface> {
+	let spreadsheet_id = env::var("SPREADSHEET_ID")?;
+
+	let mut successful_updates = 0;
+	let total_rows = statuses.len();
+
+	for (row_number, status) in statuses {
+		let range = format!(
+			"Transactions!{}{}:{}{}",
+			column, row_number, column, row_number
+		);
+		let url = format!(
+			"https://sheets.googleapis.com/v4/spreadsheets/{}/values/{}?valueInputOption=RAW&includeValuesInResponse=0",
+			spreadsheet_id, range
+		);
+		let value_range = ValueRange {
+			range,
+			values: vec![vec![Some(status)]],
+		};
+
+		let write_status = client
+			.put(&url)
+			.body(serde_json::to_string(&value_range)?)
+			.send()
+			.await?
+			.error_for_status();
+
+		if let Err(e) = write_status {
+			error!(
+				"Could not update status for row {}. Error: {}",
+				row_number, e
+			);
+			continue;
+		}
+
+		successful_updates += 1;
+	}
+
+	match successful_updates == total_rows {
+		true => Ok(()),
+		false => Err(format!(
+			"Failed to update {} out of {} rows",
+			total_rows - successful_updates,
+			total_rows
+		)
+		.into()),
+	}
+}
@@ -0,0 +1,387 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+
+use chrono::NaiveDate;
+use log::warn;
+use rust_decimal::Decimal;
+
+use crate::ErrorInterface;
+use crate::transaction::Transaction;
+
+use super::{csv::parse_csv, decode, local_midnight, parse_number};
+
+/// Both apps keep dates in Japan time.
+pub const APP_TIMEZONE: chrono_tz::Tz = chrono_tz::Asia::Tokyo;
+
+/// Household account book apps whose CSV exports we read and write.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum App {
+	/// Money Forward ME
+	MoneyForward,
+	Zaim,
+}
+
+impl App {
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"moneyforward" => Some(App::MoneyForward),
+			"zaim" => Some(App::Zaim),
+			_ => None,
+		}
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			App::MoneyForward => "moneyforward",
+			App::Zaim => "zaim",
+		}
+	}
+}
+
+/// Our categories and accounts mapped to the apps' names for them.
+#[derive(Default)]
+pub struct AppMaps {
+	/// Our category and the app's category and subcategory, in file order.
+	categories: Vec<(String, App, String, String)>,
+	/// Our account and the app's name for it.
+	accounts: Vec<(String, App, String)>,
+}
+
+impl AppMaps {
+	/// Reads the maps from `APP_CATEGORY_MAP_FILE`, with `category,app,app category,app
+	/// subcategory` lines, and `APP_ACCOUNT_MAP_FILE`, with `account,app,app account` lines. Both
+	/// may be left out, in which case names are kept as they are.
+	pub fn from_env() -> Result<Self, ErrorInterface> {
+		let read_lines = |variable: &str| -> Result<Vec<Vec<String>>, ErrorInterface> {
+			match env::var(variable) {
+				Ok(path) if !path.is_empty() => Ok(parse_csv(&fs::read_to_string(path)?)),
+				_ => Ok(vec![]),
+			}
+		};
+
+		let mut maps = AppMaps::default();
+		for (line_num, line) in read_lines("APP_CATEGORY_MAP_FILE")?.into_iter().enumerate() {
+			match line.as_slice() {
+				[category, app, app_category, rest @ ..]
+					if App::from_name(app.trim()).is_some() =>
+				{
+					maps.categories.push((
+						category.trim().to_owned(),
+						App::from_name(app.trim()).unwrap(),
+						app_category.trim().to_owned(),
+						rest.first()
+							.map(|s| s.trim().to_owned())
+							.unwrap_or_default(),
+					));
+				}
+				_ => warn!(
+					"Line {} of the app category map is not understood",
+					line_num + 1
+				),
+			}
+		}
+		for (line_num, line) in read_lines("APP_ACCOUNT_MAP_FILE")?.into_iter().enumerate() {
+			match line.as_slice() {
+				[account, app, app_account] if App::from_name(app.trim()).is_some() => {
+					maps.accounts.push((
+						account.trim().to_owned(),
+						App::from_name(app.trim()).unwrap(),
+						app_account.trim().to_owned(),
+					));
+				}
+				_ => warn!(
+					"Line {} of the app account map is not understood",
+					line_num + 1
+				),
+			}
+		}
+
+		Ok(maps)
+	}
+
+	/// Our category for the app's, looking for the subcategory first. Categories not in the map
+	/// are kept as the app's category.
+	pub fn category_from_app(&self, app: App, category: &str, subcategory: &str) -> String {
+		let find = |subcategory: &str| {
+			self.categories
+				.iter()
+				.find(|(_, a, c, s)| *a == app && c == category && s == subcategory)
+				.map(|(ours, ..)| ours.clone())
+		};
+
+		find(subcategory)
+			.or_else(|| find(""))
+			.or_else(|| {
+				self.categories
+					.iter()
+					.find(|(_, a, c, _)| *a == app && c == category)
+					.map(|(ours, ..)| ours.clone())
+			})
+			.unwrap_or(category.to_owned())
+	}
+
+	/// The app's category and subcategory for ours, from the first line for it in the map.
+	pub fn category_to_app(&self, app: App, category: &str) -> (String, String) {
+		if let Some((_, _, app_category, subcategory)) = self
+			.categories
+			.iter()
+			.find(|(ours, a, ..)| *a == app && ours == category)
+		{
+			return (app_category.clone(), subcategory.clone());
+		}
+
+		match (category.trim(), app) {
+			("", App::MoneyForward) => ("未分類".to_owned(), "未分類".to_owned()),
+			("", App::Zaim) => ("その他".to_owned(), "その他".to_owned()),
+			(category, _) => (category.to_owned(), String::new()),
+		}
+	}
+
+	pub fn account_from_app(&self, app: App, app_account: &str) -> String {
+		self.accounts
+			.iter()
+			.find(|(_, a, name)| *a == app && name == app_account.trim())
+			.map(|(ours, ..)| ours.clone())
+			.unwrap_or(app_account.trim().to_owned())
+	}
+
+	pub fn account_to_app(&self, app: App, account: &str) -> String {
+		self.accounts
+			.iter()
+			.find(|(ours, a, _)| *a == app && ours == account.trim())
+			.map(|(_, _, name)| name.clone())
+			.unwrap_or(account.trim().to_owned())
+	}
+}
+
+/// Reads an app's CSV export into transactions, each on the account and with the category the
+/// maps give for the app's names. Transfers get the transfer category, so they are paired up again
+/// when exported.
+pub fn read_app_csv(
+	app: App,
+	contents: &[u8],
+	maps: &AppMaps,
+	transfer_category: &str,
+) -> Result<Vec<Transaction>, ErrorInterface> {
+	let records = parse_csv(&decode(contents));
+	let (header, records) = records.split_first().ok_or("The file is empty")?;
+	let columns = header
+		.iter()
+		.enumerate()
+		.map(|(i, name)| (name.trim(), i))
+		.collect::<HashMap<&str, usize>>();
+
+	let mut transactions = vec![];
+	for record in records {
+		let field = |name: &str| {
+			columns
+				.get(name)
+				.and_then(|i| record.get(*i))
+				.map(|f| f.trim())
+				// Zaim writes a dash for what does not apply
+				.filter(|f| *f != "-")
+				.unwrap_or_default()
+		};
+
+		match app {
+			App::MoneyForward => {
+				transactions.push(read_moneyforward_record(&field, maps, transfer_category)?)
+			}
+			App::Zaim => transactions.extend(read_zaim_record(&field, maps, transfer_category)?),
+		}
+	}
+
+	Ok(transactions)
+}
+
+fn parse_date(text: &str) -> Result<NaiveDate, ErrorInterface> {
+	["%Y/%m/%d", "%Y-%m-%d"]
+		.iter()
+		.find_map(|f| NaiveDate::parse_from_str(text, f).ok())
+		.ok_or(format!("Unknown date format: {}", text).into())
+}
+
+fn read_moneyforward_record<'a>(
+	field: &dyn Fn(&str) -> &'a str,
+	maps: &AppMaps,
+	transfer_category: &str,
+) -> Result<Transaction, ErrorInterface> {
+	let app = App::MoneyForward;
+
+	let mut metadata = BTreeMap::new();
+	for (column, key) in [("ID", "moneyforward_id"), ("メモ", "memo")] {
+		if !field(column).is_empty() {
+			metadata.insert(key.to_owned(), field(column).to_owned());
+		}
+	}
+	let category = match field("振替") {
+		"1" => {
+			metadata.insert("kind".to_owned(), "transfer".to_owned());
+			transfer_category.to_owned()
+		}
+		_ => maps.category_from_app(app, field("大項目"), field("中項目")),
+	};
+
+	Ok(Transaction {
+		subject: Some(field("内容").to_owned()),
+		datetime: local_midnight(parse_date(field("日付"))?, APP_TIMEZONE),
+		amount: parse_number(field("金額（円）"))?,
+		account: maps.account_from_app(app, field("保有金融機関")),
+		category: Some(category),
+		metadata,
+		receipt: None,
+	})
+}
+
+/// Reads a row of a Zaim export. A transfer is one row there, so it becomes a transaction on each
+/// of the two accounts.
+fn read_zaim_record<'a>(
+	field: &dyn Fn(&str) -> &'a str,
+	maps: &AppMaps,
+	transfer_category: &str,
+) -> Result<Vec<Transaction>, ErrorInterface> {
+	let app = App::Zaim;
+	let datetime = local_midnight(parse_date(field("日付"))?, APP_TIMEZONE);
+	let subject = [field("お店"), field("品目"), field("カテゴリの内訳")]
+		.into_iter()
+		.find(|s| !s.is_empty())
+		.unwrap_or(match field("方法") {
+			"transfer" => "振替",
+			_ => "",
+		})
+		.to_owned();
+
+	let mut metadata = BTreeMap::new();
+	if !field("品目").is_empty() && field("品目") != subject {
+		metadata.insert("items".to_owned(), field("品目").to_owned());
+	}
+	if !field("メモ").is_empty() {
+		metadata.insert("memo".to_owned(), field("メモ").to_owned());
+	}
+
+	let make_transaction = |account: &str, amount: Decimal, category: Option<String>| Transaction {
+		subject: Some(subject.clone()),
+		datetime,
+		amount,
+		account: maps.account_from_app(app, account),
+		category,
+		metadata: metadata.clone(),
+		receipt: None,
+	};
+	let category = Some(maps.category_from_app(app, field("カテゴリ"), field("カテゴリの内訳")));
+
+	Ok(match field("方法") {
+		"payment" => vec![make_transaction(
+			field("支払元"),
+			-parse_number(field("支出"))?,
+			category,
+		)],
+		"income" => vec![make_transaction(
+			field("入金先"),
+			parse_number(field("収入"))?,
+			category,
+		)],
+		"transfer" => {
+			let amount = parse_number(field("振替"))?;
+			[(field("支払元"), -amount), (field("入金先"), amount)]
+				.into_iter()
+				.filter(|(account, _)| !account.is_empty())
+				.map(|(account, amount)| {
+					let mut transaction =
+						make_transaction(account, amount, Some(transfer_category.to_owned()));
+					transaction
+						.metadata
+						.insert("kind".to_owned(), "transfer".to_owned());
+					transaction
+				})
+				.collect()
+		}
+		// balance adjustments are not transactions
+		_ => vec![],
+	})
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use encoding_rs::SHIFT_JIS;
+
+	use crate::transaction::Transaction;
+
+	use super::{App, AppMaps, read_app_csv};
+
+	pub fn make_maps() -> AppMaps {
+		AppMaps {
+			categories: vec![
+				(
+					"Food".into(),
+					App::MoneyForward,
+					"食費".into(),
+					"食料品".into(),
+				),
+				("Food".into(), App::MoneyForward, "食費".into(), "".into()),
+				("Food".into(), App::Zaim, "食費".into(), "食料品".into()),
+				(
+					"Services".into(),
+					App::Zaim,
+					"水道・光熱".into(),
+					"電気料金".into(),
+				),
+			],
+			accounts: vec![
+				("Rakuten".into(), App::MoneyForward, "楽天カード".into()),
+				("Rakuten".into(), App::Zaim, "楽天カード".into()),
+				("Yuucho".into(), App::Zaim, "ゆうちょ銀行".into()),
+			],
+		}
+	}
+
+	pub fn summarize(transactions: &[Transaction]) -> Vec<String> {
+		transactions
+			.iter()
+			.map(|t| {
+				format!(
+					"{} {} {} {} {} {}",
+					t.datetime,
+					t.account,
+					t.subject.as_deref().unwrap(),
+					t.amount,
+					t.category.as_deref().unwrap_or("-"),
+					t.notes().unwrap_or("-".into())
+				)
+			})
+			.collect()
+	}
+
+	#[test]
+	fn reads_moneyforward_and_zaim_exports() {
+		let (contents, _, _) = SHIFT_JIS.encode(include_str!("fixtures/moneyforward.csv"));
+		let transactions =
+			read_app_csv(App::MoneyForward, &contents, &make_maps(), "Transfer").unwrap();
+		assert_eq!(
+			vec![
+				"2025-01-19 15:00:00 UTC Rakuten ローソン -658 Food moneyforward_id=Xk2p9",
+				"2025-01-20 15:00:00 UTC Rakuten コンビニ -120 Food memo=おやつ; moneyforward_id=Xk2q1",
+				"2025-01-26 15:00:00 UTC PayPay銀行 給与 300000 収入 moneyforward_id=Xk2r4",
+			],
+			summarize(&transactions)
+		);
+
+		let transactions = read_app_csv(
+			App::Zaim,
+			include_bytes!("fixtures/zaim.csv"),
+			&make_maps(),
+			"Transfer",
+		)
+		.unwrap();
+		assert_eq!(
+			vec![
+				"2025-01-19 15:00:00 UTC Rakuten ローソン -658 Food items=おにぎり",
+				"2025-01-24 15:00:00 UTC Yuucho 関西電力 -9876 Services -",
+				"2025-01-26 15:00:00 UTC Yuucho 振替 -50000 Transfer kind=transfer",
+				"2025-01-26 15:00:00 UTC 財布 振替 50000 Transfer kind=transfer",
+			],
+			summarize(&transactions)
+		);
+	}
+}
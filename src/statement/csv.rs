@@ -0,0 +1,66 @@
+/// Splits CSV text into records of fields. Quoted fields may hold commas, doubled quotes and line
+/// breaks. Blank lines are skipped.
+pub fn parse_csv(text: &str) -> Vec<Vec<String>> {
+	let mut records = vec![];
+	let mut record = vec![];
+	let mut field = String::new();
+	let mut in_quotes = false;
+	let mut chars = text.chars().peekable();
+
+	while let Some(c) = chars.next() {
+		match (c, in_quotes) {
+			('"', true) if chars.peek() == Some(&'"') => {
+				field.push('"');
+				chars.next();
+			}
+			('"', true) => in_quotes = false,
+			('"', false) if field.is_empty() => in_quotes = true,
+			(',', false) => record.push(std::mem::take(&mut field)),
+			('\r', false) => {}
+			('\n', false) => {
+				record.push(std::mem::take(&mut field));
+				if record.iter().any(|f| !f.is_empty()) {
+					records.push(std::mem::take(&mut record));
+				}
+				record.clear();
+			}
+			(c, _) => field.push(c),
+		}
+	}
+
+	record.push(field);
+	if record.iter().any(|f| !f.is_empty()) {
+		records.push(record);
+	}
+
+	records
+}
+
+/// Joins records into CSV text with every field quoted, ending each line with CRLF.
+pub fn write_csv(records: &[Vec<String>]) -> String {
+	records
+		.iter()
+		.map(|record| {
+			record
+				.iter()
+				.map(|f| format!("\"{}\"", f.replace('"', "\"\"")))
+				.collect::<Vec<String>>()
+				.join(",") + "\r\n"
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{parse_csv, write_csv};
+
+	#[test]
+	fn quoted_fields_keep_commas_quotes_and_line_breaks() {
+		let records = parse_csv("a,\"b,1\",\"say \"\"hi\"\"\"\r\n\r\n\"multi\nline\",,x\n");
+		assert_eq!(
+			vec![vec!["a", "b,1", "say \"hi\""], vec!["multi\nline", "", "x"],],
+			records
+		);
+		assert_eq!(records, parse_csv(&write_csv(&records)));
+	}
+}
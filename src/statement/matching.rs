@@ -0,0 +1,211 @@
+use std::collections::HashSet;
+
+use chrono::Duration;
+
+use crate::sheet::ValueRow;
+use crate::transaction::Transaction;
+
+/// How many days apart a statement row and the sheet row for it may be dated, since mails carry
+/// the time of the purchase and statements the day it was booked.
+pub const DEFAULT_DATE_TOLERANCE_DAYS: i64 = 3;
+
+/// Pairs each transaction with the sheet row for it: one of the same account and amount, dated
+/// closest within the tolerance. A row stands for one transaction at most, so two equal purchases
+/// need two rows. Rows marked as duplicates are not paired.
+pub fn pair_with_rows(
+	transactions: &[Transaction],
+	rows: &[ValueRow],
+	tolerance: Duration,
+) -> Vec<Option<usize>> {
+	let mut used = vec![false; rows.len()];
+
+	transactions
+		.iter()
+		.map(|transaction| {
+			let index =
+				rows.iter()
+					.enumerate()
+					.filter(|(i, row)| {
+						!used[*i]
+							&& !row.marked_dup() && row.account.trim() == transaction.account.trim()
+							&& row.amount == transaction.amount
+							&& (row.datetime() - transaction.datetime).abs() <= tolerance
+					})
+					.min_by_key(|(_, row)| (row.datetime() - transaction.datetime).abs())
+					.map(|(i, _)| i)?;
+			used[index] = true;
+			Some(index)
+		})
+		.collect()
+}
+
+/// Metadata keys of the IDs that banks and apps give their transactions.
+const ID_KEYS: &[&str] = &["fitid", "moneyforward_id"];
+
+/// Leaves out the transactions whose ID from the bank or app is already in the notes of a row of
+/// the same account. IDs are only unique within one account, so the same ID on another account
+/// is a different transaction.
+pub fn remove_known_ids(transactions: Vec<Transaction>, rows: &[ValueRow]) -> Vec<Transaction> {
+	let known = rows
+		.iter()
+		.flat_map(|r| {
+			let account = r.account.trim().to_owned();
+			let mut metadata = r.metadata();
+			ID_KEYS
+				.iter()
+				.filter_map(move |key| metadata.remove(*key).map(|id| (account.clone(), *key, id)))
+				.collect::<Vec<(String, &str, String)>>()
+		})
+		.collect::<HashSet<(String, &str, String)>>();
+
+	transactions
+		.into_iter()
+		.filter(|t| {
+			!ID_KEYS.iter().any(|key| {
+				t.metadata.get(*key).is_some_and(|id| {
+					known.contains(&(t.account.trim().to_owned(), *key, id.clone()))
+				})
+			})
+		})
+		.collect()
+}
+
+/// The transactions that are not on the sheet yet.
+pub fn find_missing(
+	transactions: Vec<Transaction>,
+	rows: &[ValueRow],
+	tolerance: Duration,
+) -> Vec<Transaction> {
+	let pairs = pair_with_rows(&transactions, rows, tolerance);
+
+	transactions
+		.into_iter()
+		.zip(pairs)
+		.filter(|(_, row)| row.is_none())
+		.map(|(transaction, _)| transaction)
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeMap;
+
+	use chrono::Duration;
+	use rust_decimal::Decimal;
+
+	use crate::sheet::fetch::read_amount;
+	use crate::sheet::{ValueRow, datetime_to_serial};
+	use crate::statement::{find_layout, read_statement};
+	use crate::transaction::Transaction;
+
+	use super::{find_missing, remove_known_ids};
+
+	#[test]
+	fn only_transactions_missing_from_the_sheet_are_kept() {
+		let make_transaction = |date: &str, amount: i64| Transaction {
+			subject: Some("LAWSON".into()),
+			datetime: format!("{}T00:00:00Z", date).parse().unwrap(),
+			amount: Decimal::from(amount),
+			account: "Rakuten".into(),
+			category: None,
+			metadata: BTreeMap::new(),
+			receipt: None,
+		};
+		let make_row = |row_number: usize, subject: &str, date: &str, amount: i64| ValueRow {
+			row_number,
+			account: "Rakuten".into(),
+			subject: subject.into(),
+			date_value: datetime_to_serial(&format!("{}T09:30:00Z", date).parse().unwrap()),
+			amount: Decimal::from(amount),
+			currency: "".into(),
+			category: "".into(),
+			notes: "".into(),
+		};
+
+		let transactions = vec![
+			make_transaction("2025-01-20", -658),
+			make_transaction("2025-01-20", -658),
+			make_transaction("2025-01-22", -1200),
+			make_transaction("2025-01-25", -300),
+		];
+		let rows = vec![
+			make_row(2, "ローソン", "2025-01-19", -658),
+			make_row(3, "?dupof2 ローソン", "2025-01-19", -658),
+			make_row(4, "Shop", "2025-01-10", -1200),
+			make_row(5, "Kiosk", "2025-01-26", -300),
+		];
+
+		let missing = find_missing(transactions, &rows, Duration::days(3));
+		assert_eq!(
+			vec![
+				"2025-01-20 00:00:00 UTC -658",
+				"2025-01-22 00:00:00 UTC -1200"
+			],
+			missing
+				.iter()
+				.map(|t| format!("{} {}", t.datetime, t.amount))
+				.collect::<Vec<String>>()
+		);
+	}
+
+	#[test]
+	fn importing_the_same_statement_twice_appends_nothing() {
+		let contents = include_bytes!("fixtures/ocbc.csv");
+		let transactions = read_statement(find_layout("ocbc").unwrap(), contents, "OCBC").unwrap();
+
+		// what the sheet hands back for the rows written by the first import
+		let rows = transactions
+			.iter()
+			.enumerate()
+			.map(|(i, t)| ValueRow {
+				row_number: i + 2,
+				account: t.account.clone(),
+				subject: t.subject.clone().unwrap_or_default(),
+				date_value: datetime_to_serial(&t.datetime),
+				amount: read_amount(&serde_json::from_str(&t.amount.to_string()).unwrap()),
+				currency: "".into(),
+				category: "".into(),
+				notes: "".into(),
+			})
+			.collect::<Vec<ValueRow>>();
+
+		assert!(rows.iter().any(|r| !r.amount.fract().is_zero()));
+		assert!(find_missing(transactions, &rows, Duration::days(3)).is_empty());
+	}
+
+	#[test]
+	fn known_ids_are_only_matched_on_the_same_account() {
+		let make_transaction = |account: &str, fitid: &str| Transaction {
+			subject: Some("Shop".into()),
+			datetime: "2025-01-20T00:00:00Z".parse().unwrap(),
+			amount: Decimal::from(-1000),
+			account: account.into(),
+			category: None,
+			metadata: BTreeMap::from([("fitid".to_owned(), fitid.to_owned())]),
+			receipt: None,
+		};
+		let rows = vec![ValueRow {
+			row_number: 2,
+			account: "OCBC".into(),
+			subject: "Shop".into(),
+			date_value: datetime_to_serial(&"2025-01-20T00:00:00Z".parse().unwrap()),
+			amount: Decimal::from(-1000),
+			currency: "".into(),
+			category: "".into(),
+			notes: "fitid=2025012001".into(),
+		}];
+
+		let transactions = vec![
+			make_transaction("OCBC", "2025012001"),
+			make_transaction("BCA", "2025012001"),
+			make_transaction("OCBC", "2025012002"),
+		];
+		assert_eq!(
+			vec!["BCA 2025012001", "OCBC 2025012002"],
+			remove_known_ids(transactions, &rows)
+				.iter()
+				.map(|t| format!("{} {}", t.account, t.metadata["fitid"]))
+				.collect::<Vec<String>>()
+		);
+	}
+}
@@ -0,0 +1,310 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+use encoding_rs::SHIFT_JIS;
+use log::warn;
+use regex::Regex;
+use rust_decimal::Decimal;
+
+use crate::ErrorInterface;
+use crate::transaction::Transaction;
+
+pub mod apps;
+pub mod csv;
+pub mod matching;
+pub mod ofx;
+pub mod reconcile;
+
+/// How the amount of a row is written.
+enum AmountColumns {
+	/// One column with what was charged, so spending is positive.
+	Charge(&'static str),
+	/// Money going out and money coming in, in columns of their own.
+	DebitCredit {
+		debit: &'static str,
+		credit: &'static str,
+	},
+	/// One column with a `DB` or `CR` suffix, e.g. `57,300.00 DB`.
+	Suffixed(&'static str),
+}
+
+/// The columns of one bank's statement CSV, found by their header names.
+pub struct StatementLayout {
+	pub name: &'static str,
+	date_column: &'static str,
+	date_formats: &'static [&'static str],
+	/// Whether the dates leave out the year, which is then the one that puts the date inside the
+	/// statement period above the header.
+	dates_without_year: bool,
+	subject_column: &'static str,
+	amount: AmountColumns,
+	/// Columns noted down as metadata, with their metadata keys.
+	metadata_columns: &'static [(&'static str, &'static str)],
+	pub timezone: chrono_tz::Tz,
+}
+
+pub const LAYOUTS: &[StatementLayout] = &[
+	StatementLayout {
+		name: "rakuten-card",
+		date_column: "利用日",
+		date_formats: &["%Y/%m/%d", "%Y-%m-%d"],
+		dates_without_year: false,
+		subject_column: "利用店名・商品名",
+		amount: AmountColumns::Charge("利用金額"),
+		metadata_columns: &[("利用者", "card_user"), ("支払方法", "payment_method")],
+		timezone: chrono_tz::Asia::Tokyo,
+	},
+	StatementLayout {
+		name: "ocbc",
+		date_column: "Transaction Date",
+		date_formats: &["%d/%m/%Y", "%d %b %Y", "%Y-%m-%d"],
+		dates_without_year: false,
+		subject_column: "Description",
+		amount: AmountColumns::DebitCredit {
+			debit: "Debit",
+			credit: "Credit",
+		},
+		metadata_columns: &[],
+		timezone: chrono_tz::Asia::Jakarta,
+	},
+	StatementLayout {
+		name: "bca",
+		date_column: "Tanggal Transaksi",
+		date_formats: &["%d/%m/%Y"],
+		dates_without_year: true,
+		subject_column: "Keterangan",
+		amount: AmountColumns::Suffixed("Jumlah"),
+		metadata_columns: &[],
+		timezone: chrono_tz::Asia::Jakarta,
+	},
+];
+
+pub fn find_layout(name: &str) -> Option<&'static StatementLayout> {
+	LAYOUTS.iter().find(|l| l.name == name)
+}
+
+/// Decodes a statement file, which is UTF-8 (with or without a BOM) or, failing that, Shift_JIS.
+pub fn decode(contents: &[u8]) -> String {
+	let contents = contents.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(contents);
+	match std::str::from_utf8(contents) {
+		Ok(text) => text.to_owned(),
+		Err(_) => SHIFT_JIS.decode(contents).0.into_owned(),
+	}
+}
+
+/// Reads the rows of a statement into transactions for the account, dated at midnight of the
+/// bank's time zone. Rows without a date, such as totals, are left out.
+pub fn read_statement(
+	layout: &StatementLayout,
+	contents: &[u8],
+	account: &str,
+) -> Result<Vec<Transaction>, ErrorInterface> {
+	let records = csv::parse_csv(&decode(contents));
+	let header_index = records
+		.iter()
+		.position(|r| r.iter().any(|f| f.trim() == layout.date_column))
+		.ok_or(format!("No {} header found", layout.date_column))?;
+	let header = &records[header_index];
+	let column = |name: &str| -> Result<usize, ErrorInterface> {
+		header
+			.iter()
+			.position(|f| f.trim() == name)
+			.ok_or(format!("No {} column found", name).into())
+	};
+
+	let period = match layout.dates_without_year {
+		true => Some(find_period(&records[..header_index]).ok_or("No statement period found")?),
+		false => None,
+	};
+
+	let date_column = column(layout.date_column)?;
+	let subject_column = column(layout.subject_column)?;
+	let metadata_columns = layout
+		.metadata_columns
+		.iter()
+		.map(|(name, key)| Ok((column(name)?, *key)))
+		.collect::<Result<Vec<(usize, &str)>, ErrorInterface>>()?;
+
+	let mut transactions = vec![];
+	for record in &records[header_index + 1..] {
+		let field = |index: usize| record.get(index).map(|f| f.trim()).unwrap_or_default();
+
+		let date_string = field(date_column).trim_start_matches('\'');
+		if date_string.is_empty() {
+			continue;
+		}
+		let parse_date = |date_string: &str| {
+			layout
+				.date_formats
+				.iter()
+				.find_map(|f| NaiveDate::parse_from_str(date_string, f).ok())
+		};
+		let date = match period {
+			// a period such as 15/12/2024 - 14/01/2025 has 20/12 in 2024 and 05/01 in 2025
+			Some((start, end)) => [start.year(), end.year()]
+				.iter()
+				.filter_map(|year| parse_date(&format!("{}/{}", date_string, year)))
+				.find(|date| (start..=end).contains(date)),
+			None => parse_date(date_string),
+		};
+		let Some(date) = date else {
+			warn!("Skipping statement row with unknown date: {:?}", record);
+			continue;
+		};
+
+		let amount = read_amount(&layout.amount, &column, &field)?;
+
+		let mut metadata = BTreeMap::new();
+		metadata.insert("statement".to_owned(), layout.name.to_owned());
+		for (index, key) in &metadata_columns {
+			if !field(*index).is_empty() {
+				metadata.insert(key.to_string(), field(*index).to_owned());
+			}
+		}
+
+		transactions.push(Transaction {
+			subject: Some(field(subject_column).to_owned()),
+			datetime: local_midnight(date, layout.timezone),
+			amount,
+			account: account.to_owned(),
+			category: None,
+			metadata,
+			receipt: None,
+		});
+	}
+
+	Ok(transactions)
+}
+
+fn read_amount<'a>(
+	amount: &AmountColumns,
+	column: &dyn Fn(&str) -> Result<usize, ErrorInterface>,
+	field: &dyn Fn(usize) -> &'a str,
+) -> Result<Decimal, ErrorInterface> {
+	Ok(match amount {
+		AmountColumns::Charge(name) => -parse_number(field(column(name)?))?,
+		AmountColumns::DebitCredit { debit, credit } => {
+			parse_number(field(column(credit)?))? - parse_number(field(column(debit)?))?
+		}
+		AmountColumns::Suffixed(name) => {
+			let text = field(column(name)?);
+			match text.strip_suffix("DB") {
+				Some(debit) => -parse_number(debit)?,
+				None => parse_number(text.trim_end_matches("CR"))?,
+			}
+		}
+	})
+}
+
+/// Parses a number such as `1,234,567.00`, taking an empty field as zero.
+pub fn parse_number(text: &str) -> Result<Decimal, ErrorInterface> {
+	let text = text.trim().replace(",", "");
+	if text.is_empty() {
+		return Ok(Decimal::ZERO);
+	}
+
+	Ok(Decimal::from_str_exact(&text)?.normalize())
+}
+
+/// Finds both ends of a period such as `Periode : 01/01/2025 - 31/01/2025`.
+fn find_period(records: &[Vec<String>]) -> Option<(NaiveDate, NaiveDate)> {
+	let regex = Regex::new(r"(\d{2}/\d{2}/\d{4})\s*-\s*(\d{2}/\d{2}/\d{4})").unwrap();
+	let captures = records.iter().flatten().find_map(|f| regex.captures(f))?;
+	let start = NaiveDate::parse_from_str(&captures[1], "%d/%m/%Y").ok()?;
+	let end = NaiveDate::parse_from_str(&captures[2], "%d/%m/%Y").ok()?;
+
+	Some((start, end))
+}
+
+fn local_midnight(date: NaiveDate, timezone: chrono_tz::Tz) -> DateTime<Utc> {
+	timezone
+		.from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+		.unwrap()
+		.with_timezone(&Utc)
+}
+
+#[cfg(test)]
+mod tests {
+	use encoding_rs::SHIFT_JIS;
+
+	use super::{find_layout, read_statement};
+
+	fn summarize(transactions: &[crate::transaction::Transaction]) -> Vec<String> {
+		transactions
+			.iter()
+			.map(|t| {
+				format!(
+					"{} {} {} {}",
+					t.datetime,
+					t.subject.as_deref().unwrap(),
+					t.amount,
+					t.notes().unwrap()
+				)
+			})
+			.collect()
+	}
+
+	#[test]
+	fn reads_shift_jis_rakuten_card_statement() {
+		let (contents, _, _) = SHIFT_JIS.encode(include_str!("fixtures/rakuten_card.csv"));
+		let transactions =
+			read_statement(find_layout("rakuten-card").unwrap(), &contents, "Rakuten").unwrap();
+
+		assert_eq!(
+			vec![
+				"2025-01-19 15:00:00 UTC ＡＭＡＺＯＮ．ＣＯ．ＪＰ -4046 card_user=本人; payment_method=1回払い; statement=rakuten-card",
+				"2025-01-20 15:00:00 UTC ローソン　神戸三宮店 -658 card_user=家族; payment_method=1回払い; statement=rakuten-card",
+				"2025-01-24 15:00:00 UTC ＡＭＡＺＯＮ．ＣＯ．ＪＰ 1200 card_user=本人; payment_method=1回払い; statement=rakuten-card",
+			],
+			summarize(&transactions)
+		);
+	}
+
+	#[test]
+	fn reads_bank_statements_with_debit_and_credit() {
+		let transactions = read_statement(
+			find_layout("ocbc").unwrap(),
+			include_bytes!("fixtures/ocbc.csv"),
+			"OCBC",
+		)
+		.unwrap();
+		assert_eq!(
+			vec![
+				"2025-01-19 17:00:00 UTC QRIS KOPI KENANGAN -57300 statement=ocbc",
+				"2025-01-20 17:00:00 UTC TRANSFER FROM BUDI 1250000.5 statement=ocbc",
+			],
+			summarize(&transactions)
+		);
+
+		let transactions = read_statement(
+			find_layout("bca").unwrap(),
+			include_bytes!("fixtures/bca.csv"),
+			"BCA",
+		)
+		.unwrap();
+		assert_eq!(
+			vec![
+				"2025-01-19 17:00:00 UTC TRSF E-BANKING DB GOJEK -57300 statement=bca",
+				"2025-01-24 17:00:00 UTC BUNGA 1234.56 statement=bca",
+			],
+			summarize(&transactions)
+		);
+	}
+
+	#[test]
+	fn dates_without_year_take_the_year_inside_the_period() {
+		let transactions = read_statement(
+			find_layout("bca").unwrap(),
+			include_bytes!("fixtures/bca_year_end.csv"),
+			"BCA",
+		)
+		.unwrap();
+		assert_eq!(
+			vec![
+				"2024-12-27 17:00:00 UTC TRSF E-BANKING DB TOKOPEDIA -150000 statement=bca",
+				"2025-01-04 17:00:00 UTC BUNGA 1234.56 statement=bca",
+			],
+			summarize(&transactions)
+		);
+	}
+}
@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use regex::Regex;
+use rust_decimal::Decimal;
+
+use crate::ErrorInterface;
+use crate::transaction::Transaction;
+
+use super::decode;
+
+/// Reads the `STMTTRN` records of an OFX or QFX file, either the SGML of OFX 1 or the XML of
+/// OFX 2, into transactions for the account. Each keeps its `FITID` in the metadata.
+pub fn read_ofx(contents: &[u8], account: &str) -> Result<Vec<Transaction>, ErrorInterface> {
+	let text = decode(contents);
+
+	let mut transactions = vec![];
+	for record in text.split("<STMTTRN>").skip(1) {
+		let record = record.split("</STMTTRN>").next().unwrap_or_default();
+		let fields = read_elements(record)
+			.into_iter()
+			.collect::<BTreeMap<_, _>>();
+		let field = |tag: &str| {
+			fields
+				.get(tag)
+				.map(|v| v.as_str())
+				.ok_or(format!("No {} found in STMTTRN", tag))
+		};
+
+		let subject = match (fields.get("NAME"), fields.get("MEMO")) {
+			(Some(name), _) if !name.is_empty() => name.clone(),
+			(_, Some(memo)) => memo.clone(),
+			_ => String::new(),
+		};
+
+		let mut metadata = BTreeMap::new();
+		metadata.insert("fitid".to_owned(), field("FITID")?.to_owned());
+		if let Some(memo) = fields.get("MEMO").filter(|m| **m != subject) {
+			metadata.insert("memo".to_owned(), memo.clone());
+		}
+
+		transactions.push(Transaction {
+			subject: Some(subject),
+			datetime: parse_ofx_datetime(field("DTPOSTED")?)?,
+			amount: Decimal::from_str_exact(&field("TRNAMT")?.replace(",", ""))?.normalize(),
+			account: account.to_owned(),
+			category: None,
+			metadata,
+			receipt: None,
+		});
+	}
+
+	Ok(transactions)
+}
+
+/// The time zone the statement's dates are in, from the offset of its `DTSTART` or, failing that,
+/// the first `DTPOSTED` with one, so that a period of days can be read the way the bank meant it.
+/// Dates without an offset are in UTC.
+pub fn read_ofx_timezone(contents: &[u8]) -> Result<chrono_tz::Tz, ErrorInterface> {
+	let elements = read_elements(&decode(contents));
+	let zone = ["DTSTART", "DTPOSTED"].iter().find_map(|tag| {
+		elements
+			.iter()
+			.filter(|(t, _)| t == tag)
+			.find_map(|(_, value)| value.split_once('[').map(|(_, zone)| zone))
+	});
+	let Some(zone) = zone else {
+		return Ok(chrono_tz::UTC);
+	};
+
+	let offset = parse_offset(zone.trim_end_matches(']'))?;
+	let hours = offset.local_minus_utc() / 3600;
+	if offset.local_minus_utc() % 3600 != 0 {
+		return Err(format!("Time zone offset {} is not in whole hours", zone).into());
+	}
+	// the Etc zones are named the POSIX way, with the sign reversed
+	Ok(format!("Etc/GMT{:+}", -hours).parse::<chrono_tz::Tz>()?)
+}
+
+/// The elements that hold a value, in order. SGML elements are not closed, so a value ends at the
+/// next tag either way.
+fn read_elements(text: &str) -> Vec<(String, String)> {
+	let regex = Regex::new(r"<([A-Za-z0-9.]+)>([^<]*)").unwrap();
+	regex
+		.captures_iter(text)
+		.map(|c| (c[1].to_uppercase(), unescape(c[2].trim())))
+		.filter(|(_, value)| !value.is_empty())
+		.collect()
+}
+
+fn unescape(text: &str) -> String {
+	text.replace("&lt;", "<")
+		.replace("&gt;", ">")
+		.replace("&quot;", "\"")
+		.replace("&apos;", "'")
+		.replace("&amp;", "&")
+}
+
+/// Parses an OFX datetime such as `20250120`, `20250120101500.000` or
+/// `20250120101500[+9:JST]`. Without an offset, the time is in UTC.
+fn parse_ofx_datetime(text: &str) -> Result<DateTime<Utc>, ErrorInterface> {
+	let (datetime, offset) = match text.split_once('[') {
+		Some((datetime, zone)) => (datetime, Some(zone.trim_end_matches(']'))),
+		None => (text, None),
+	};
+	let datetime = datetime.split('.').next().unwrap_or_default();
+
+	let naive = match datetime.len() {
+		8 => NaiveDate::parse_from_str(datetime, "%Y%m%d")?
+			.and_hms_opt(0, 0, 0)
+			.unwrap(),
+		12 => NaiveDateTime::parse_from_str(datetime, "%Y%m%d%H%M")?,
+		_ => NaiveDateTime::parse_from_str(datetime, "%Y%m%d%H%M%S")?,
+	};
+
+	let offset = match offset {
+		Some(offset) => parse_offset(offset)?,
+		None => FixedOffset::east_opt(0).unwrap(),
+	};
+
+	Ok(offset
+		.from_local_datetime(&naive)
+		.single()
+		.ok_or("Ambiguous datetime")?
+		.with_timezone(&Utc))
+}
+
+/// Parses the offset part of an OFX datetime, such as `+9:JST` or `-5`.
+fn parse_offset(zone: &str) -> Result<FixedOffset, ErrorInterface> {
+	let offset_hours = zone
+		.split(':')
+		.next()
+		.unwrap_or_default()
+		.parse::<f64>()
+		.map_err(|_| format!("Unknown time zone offset: {}", zone))?;
+
+	Ok(FixedOffset::east_opt((offset_hours * 3600.0) as i32)
+		.ok_or(format!("Time zone offset out of range: {}", offset_hours))?)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{read_ofx, read_ofx_timezone};
+
+	#[test]
+	fn reads_sgml_and_xml_statements() {
+		for contents in [
+			&include_bytes!("fixtures/statement.ofx")[..],
+			&include_bytes!("fixtures/statement.qfx")[..],
+		] {
+			let transactions = read_ofx(contents, "OCBC").unwrap();
+			assert_eq!(
+				vec![
+					"2025-01-20 01:15:00 UTC KOPI KENANGAN -57300 fitid=2025012001",
+					"2025-01-21 00:00:00 UTC Transfer from Budi & Co 1250000.5 fitid=2025012102; memo=Rent",
+				],
+				transactions
+					.iter()
+					.map(|t| format!(
+						"{} {} {} {}",
+						t.datetime,
+						t.subject.as_deref().unwrap(),
+						t.amount,
+						t.notes().unwrap()
+					))
+					.collect::<Vec<String>>()
+			);
+			assert_eq!("Etc/GMT-7", read_ofx_timezone(contents).unwrap().name());
+		}
+
+		let jst = b"<DTSTART>20250201000000[+9:JST]<DTPOSTED>20250201000000[+9:JST]";
+		assert_eq!("Etc/GMT-9", read_ofx_timezone(jst).unwrap().name());
+		assert_eq!(
+			"UTC",
+			read_ofx_timezone(b"<DTPOSTED>20250201").unwrap().name()
+		);
+	}
+}
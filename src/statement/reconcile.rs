@@ -0,0 +1,244 @@
+use std::fmt;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::sheet::ValueRow;
+use crate::transaction::Transaction;
+
+use super::matching::pair_with_rows;
+
+/// How far apart a sheet row may be dated from its statement row before it counts as drift.
+const SAME_DAY_TOLERANCE: Duration = Duration::days(1);
+
+/// Where the sheet and a statement agree or disagree.
+pub enum Finding {
+	Matched(ValueRow, Transaction),
+	/// The same amount, but dated more than a day apart.
+	DateDrift(ValueRow, Transaction),
+	/// Dated around the same day, but for a different amount.
+	AmountMismatch(ValueRow, Transaction),
+	/// On the statement but not on the sheet.
+	Missing(Transaction),
+	/// On the sheet but not on the statement.
+	Extra(ValueRow),
+}
+
+impl Finding {
+	/// The row and the status to write next to it, for findings about a row of the sheet.
+	pub fn status(&self) -> Option<(usize, String)> {
+		match self {
+			Finding::Matched(row, _) => Some((row.row_number, "ok".to_owned())),
+			Finding::DateDrift(row, t) => Some((
+				row.row_number,
+				format!("ok, statement date {}", t.datetime.format("%Y-%m-%d")),
+			)),
+			Finding::AmountMismatch(row, t) => Some((
+				row.row_number,
+				format!("amount differs, statement {}", t.amount),
+			)),
+			Finding::Missing(_) => None,
+			Finding::Extra(row) => Some((row.row_number, "not on statement".to_owned())),
+		}
+	}
+}
+
+impl fmt::Display for Finding {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let describe_row = |row: &ValueRow| {
+			format!(
+				"row {} {} {} {}",
+				row.row_number,
+				row.datetime().format("%Y-%m-%d"),
+				row.amount,
+				row.subject
+			)
+		};
+		let describe_transaction = |t: &Transaction| {
+			format!(
+				"{} {} {}",
+				t.datetime.format("%Y-%m-%d"),
+				t.amount,
+				t.subject.as_deref().unwrap_or_default()
+			)
+		};
+
+		match self {
+			Finding::Matched(row, _) => write!(f, "OK       {}", describe_row(row)),
+			Finding::DateDrift(row, t) => write!(
+				f,
+				"DRIFT    {} <> statement {}",
+				describe_row(row),
+				describe_transaction(t)
+			),
+			Finding::AmountMismatch(row, t) => write!(
+				f,
+				"AMOUNT   {} <> statement {}",
+				describe_row(row),
+				describe_transaction(t)
+			),
+			Finding::Missing(t) => write!(f, "MISSING  statement {}", describe_transaction(t)),
+			Finding::Extra(row) => write!(f, "EXTRA    {}", describe_row(row)),
+		}
+	}
+}
+
+/// Compares the statement's transactions with the account's rows, both limited to the period
+/// from `start` up to `end`. Rows marked as duplicates are left out.
+pub fn reconcile(
+	transactions: Vec<Transaction>,
+	rows: &[ValueRow],
+	account: &str,
+	(start, end): (DateTime<Utc>, DateTime<Utc>),
+	tolerance: Duration,
+) -> Vec<Finding> {
+	let in_period = |datetime: DateTime<Utc>| datetime >= start && datetime < end;
+	let transactions = transactions
+		.into_iter()
+		.filter(|t| in_period(t.datetime))
+		.collect::<Vec<Transaction>>();
+	let rows = rows
+		.iter()
+		.filter(|r| {
+			r.account.trim() == account.trim() && !r.marked_dup() && in_period(r.datetime())
+		})
+		.cloned()
+		.collect::<Vec<ValueRow>>();
+
+	let pairs = pair_with_rows(&transactions, &rows, tolerance);
+	let mut row_used = vec![false; rows.len()];
+	let mut findings = vec![];
+	let mut unpaired = vec![];
+	for (transaction, pair) in transactions.into_iter().zip(pairs) {
+		let Some(index) = pair else {
+			unpaired.push(transaction);
+			continue;
+		};
+		row_used[index] = true;
+
+		let row = rows[index].clone();
+		match (row.datetime() - transaction.datetime).abs() <= SAME_DAY_TOLERANCE {
+			true => findings.push(Finding::Matched(row, transaction)),
+			false => findings.push(Finding::DateDrift(row, transaction)),
+		}
+	}
+
+	// what is left on both sides around the same day is probably the same, for another amount
+	for transaction in unpaired {
+		let index = rows
+			.iter()
+			.enumerate()
+			.filter(|(i, row)| {
+				!row_used[*i] && (row.datetime() - transaction.datetime).abs() <= SAME_DAY_TOLERANCE
+			})
+			.min_by_key(|(_, row)| {
+				(
+					(row.datetime() - transaction.datetime).abs(),
+					(row.amount - transaction.amount).abs(),
+				)
+			})
+			.map(|(i, _)| i);
+
+		match index {
+			Some(index) => {
+				row_used[index] = true;
+				findings.push(Finding::AmountMismatch(rows[index].clone(), transaction));
+			}
+			None => findings.push(Finding::Missing(transaction)),
+		}
+	}
+
+	for (row, used) in rows.into_iter().zip(row_used) {
+		if !used {
+			findings.push(Finding::Extra(row));
+		}
+	}
+
+	findings
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeMap;
+
+	use chrono::Duration;
+	use rust_decimal::Decimal;
+
+	use crate::sheet::{ValueRow, datetime_to_serial};
+	use crate::transaction::Transaction;
+
+	use super::reconcile;
+
+	#[test]
+	fn reports_every_kind_of_disagreement() {
+		let make_transaction = |date: &str, amount: i64| Transaction {
+			subject: Some("SHOP".into()),
+			datetime: format!("{}T00:00:00Z", date).parse().unwrap(),
+			amount: Decimal::from(amount),
+			account: "Rakuten".into(),
+			category: None,
+			metadata: BTreeMap::new(),
+			receipt: None,
+		};
+		let make_row = |row_number: usize, account: &str, date: &str, amount: i64| ValueRow {
+			row_number,
+			account: account.into(),
+			subject: "Shop".into(),
+			date_value: datetime_to_serial(&format!("{}T09:30:00Z", date).parse().unwrap()),
+			amount: Decimal::from(amount),
+			currency: "".into(),
+			category: "".into(),
+			notes: "".into(),
+		};
+
+		let transactions = vec![
+			make_transaction("2025-01-05", -500),
+			make_transaction("2025-01-10", -1000),
+			make_transaction("2025-01-15", -2000),
+			make_transaction("2025-01-20", -3000),
+			make_transaction("2025-02-01", -9999),
+		];
+		let rows = vec![
+			make_row(2, "Rakuten", "2025-01-05", -500),
+			make_row(3, "Rakuten", "2025-01-12", -1000),
+			make_row(4, "Rakuten", "2025-01-15", -2200),
+			make_row(5, "Rakuten", "2025-01-25", -4000),
+			make_row(6, "PayPay", "2025-01-20", -3000),
+		];
+
+		let findings = reconcile(
+			transactions,
+			&rows,
+			"Rakuten",
+			(
+				"2025-01-01T00:00:00Z".parse().unwrap(),
+				"2025-02-01T00:00:00Z".parse().unwrap(),
+			),
+			Duration::days(3),
+		);
+		assert_eq!(
+			vec![
+				"OK       row 2 2025-01-05 -500 Shop",
+				"DRIFT    row 3 2025-01-12 -1000 Shop <> statement 2025-01-10 -1000 SHOP",
+				"AMOUNT   row 4 2025-01-15 -2200 Shop <> statement 2025-01-15 -2000 SHOP",
+				"MISSING  statement 2025-01-20 -3000 SHOP",
+				"EXTRA    row 5 2025-01-25 -4000 Shop",
+			],
+			findings
+				.iter()
+				.map(|f| f.to_string())
+				.collect::<Vec<String>>()
+		);
+		assert_eq!(
+			vec![
+				(2, "ok".to_owned()),
+				(3, "ok, statement date 2025-01-10".to_owned()),
+				(4, "amount differs, statement -2000".to_owned()),
+				(5, "not on statement".to_owned()),
+			],
+			findings
+				.iter()
+				.filter_map(|f| f.status())
+				.collect::<Vec<(usize, String)>>()
+		);
+	}
+}
@@ -1,22 +1,51 @@
-use serde::Deserialize;
+use std::collections::BTreeMap;
 
-#[derive(Deserialize, Clone)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Transaction {
 	pub subject: Option<String>,
 	pub datetime: chrono::DateTime<chrono::Utc>,
 	pub amount: rust_decimal::Decimal,
 	pub account: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub category: Option<String>,
+	/// Extra facts about where the transaction came from, written to the notes column.
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub metadata: BTreeMap<String, String>,
+	/// Where the receipt attached to the mail was archived, relative to the archive directory.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub receipt: Option<String>,
+}
+
+impl Transaction {
+	/// The metadata as `key=value` pairs separated by semicolons, or `None` if there is none.
+	pub fn notes(&self) -> Option<String> {
+		if self.metadata.is_empty() {
+			return None;
+		}
+
+		let pairs = self
+			.metadata
+			.iter()
+			.map(|(key, value)| format!("{}={}", key, value))
+			.collect::<Vec<String>>();
+		Some(pairs.join("; "))
+	}
 }
 
 impl std::fmt::Debug for Transaction {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		write!(
 			f,
-			"--- Transaction ---\nSubject: {}\nDatetime: {}\nAmount: {}\nAccount: {}\n-------------------",
+			"--- Transaction ---\nSubject: {}\nDatetime: {}\nAmount: {}\nAccount: {}\nCategory: {}\nNotes: {}\nReceipt: {}\n-------------------",
 			self.subject.as_ref().unwrap_or(&"-".to_owned()),
 			self.datetime,
 			self.amount,
 			self.account,
+			self.category.as_deref().unwrap_or("-"),
+			self.notes().unwrap_or("-".to_owned()),
+			self.receipt.as_deref().unwrap_or("-"),
 		)
 	}
 }